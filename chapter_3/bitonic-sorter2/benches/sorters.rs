@@ -0,0 +1,170 @@
+// bitonic-sorter2の各ソートと、標準ライブラリやrayonのソートを比較するベンチマーク
+// cargo benchで実行する。要素数の上限は環境変数BENCH_MAX_EXPで変えられる(既定は20)
+// 既定の上限でも、すべての組み合わせを計測するには時間がかかるので、名前で絞り込むとよい
+//
+//   BENCH_MAX_EXP=16 cargo bench -- 'u32/random'
+//   BENCH_MAX_EXP=24 cargo bench -- 'u32/random/fourth'
+
+use bitonic_sorter2::SortOrder::Ascending;
+use bitonic_sorter2::utils::{new_vec, Pattern, RandomValue};
+use bitonic_sorter2::{batch, fourth, iterative, odd_even, second, simd, third};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use rayon::prelude::*;
+use std::time::Duration;
+
+const MIN_EXP: usize = 10;
+const MAX_EXP: usize = 20;
+
+// 2^10からBENCH_MAX_EXPで指定した2のべき乗(既定は2^20)まで、要素数を返す
+// second、third、odd_evenは順次処理なので、2^24要素のStringやStudentでは1回に数十秒かかる
+fn sizes() -> Vec<usize> {
+    let max_exp = std::env::var("BENCH_MAX_EXP")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(MAX_EXP);
+    (MIN_EXP..=max_exp).step_by(2).map(|exp| 1 << exp).collect()
+}
+
+// 計測する入力データの並び
+const PATTERNS: [(&str, Pattern); 6] = [
+    ("random", Pattern::Random),
+    ("sorted", Pattern::Sorted),
+    ("reversed", Pattern::ReverseSorted),
+    ("nearly_sorted", Pattern::NearlySorted { swaps: 16 }),
+    ("many_duplicates", Pattern::FewUnique { unique: 16 }),
+    ("zipf", Pattern::Zipf { unique: 1024, exponent: 1.2 }),
+];
+
+// 毎回同じデータで計測するため、シード値を固定する
+const SEED: u64 = 0;
+
+// Studentのような、文字列を含む構造体
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Student {
+    last_name: String,
+    first_name: String,
+    age: u8,
+}
+
+impl Student {
+    fn from_u32(v: u32) -> Self {
+        Self {
+            last_name: format!("{:05}", v % 50_000),
+            first_name: format!("{:08x}", v),
+            age: (v % 100) as u8,
+        }
+    }
+}
+
+// 要素の型Tについて、すべての並び・要素数・ソートの組み合わせを計測する
+// 生成したデータ列(型U)をconvertで要素の型Tに変換してから計測する
+fn bench_sorters<U, T, G>(c: &mut Criterion, type_name: &str, convert: G)
+    where U: RandomValue,
+          T: Ord + Send + Clone,
+          G: Fn(Vec<U>) -> Vec<T>
+{
+    for &(pattern_name, pattern) in &PATTERNS {
+        let mut group = c.benchmark_group(format!("{}/{}", type_name, pattern_name));
+        // 大きな入力では1回の計測に時間がかかるので、サンプル数を減らす
+        group.sample_size(10);
+        group.warm_up_time(Duration::from_millis(500));
+
+        for n in sizes() {
+            let input = convert(new_vec(n, pattern, SEED));
+            group.throughput(Throughput::Elements(n as u64));
+
+            bench_one(&mut group, "second", n, &input, |x| second::sort(x, &Ascending).unwrap());
+            bench_one(&mut group, "third", n, &input, |x| third::sort(x, &Ascending).unwrap());
+            bench_one(&mut group, "fourth", n, &input, |x| fourth::sort(x, &Ascending).unwrap());
+            bench_one(&mut group, "iterative", n, &input, |x| iterative::sort(x, &Ascending).unwrap());
+            bench_one(&mut group, "odd_even", n, &input, |x| odd_even::sort(x, &Ascending).unwrap());
+            bench_one(&mut group, "std_sort_unstable", n, &input, |x| x.sort_unstable());
+            bench_one(&mut group, "rayon_par_sort", n, &input, |x| x.par_sort());
+        }
+        group.finish();
+    }
+}
+
+// SIMD版はu32などのプリミティブ型だけが対象なので、別に計測する
+// 同じグループのthirdと比べると、分岐のない比較交換の効果がわかる
+fn bench_simd(c: &mut Criterion) {
+    for &(pattern_name, pattern) in &PATTERNS {
+        let mut group = c.benchmark_group(format!("u32/{}", pattern_name));
+        group.sample_size(10);
+        group.warm_up_time(Duration::from_millis(500));
+
+        for n in sizes() {
+            let input: Vec<u32> = new_vec(n, pattern, SEED);
+            group.throughput(Throughput::Elements(n as u64));
+            bench_one(&mut group, "simd", n, &input, |x| simd::sort(x, &Ascending).unwrap());
+        }
+        group.finish();
+    }
+}
+
+// f32はOrdを実装しないので、thirdと標準ライブラリにはf32::total_cmpを渡してsimdと比べる
+fn bench_f32(c: &mut Criterion) {
+    for &(pattern_name, pattern) in &PATTERNS {
+        let mut group = c.benchmark_group(format!("f32/{}", pattern_name));
+        group.sample_size(10);
+        group.warm_up_time(Duration::from_millis(500));
+
+        for n in sizes() {
+            let input: Vec<f64> = new_vec(n, pattern, SEED);
+            let input: Vec<f32> = input.into_iter().map(|v| v as f32).collect();
+            group.throughput(Throughput::Elements(n as u64));
+            bench_one(&mut group, "simd", n, &input, |x| simd::sort(x, &Ascending).unwrap());
+            bench_one(&mut group, "third", n, &input,
+                      |x| third::sort_by(x, &|a, b| a.total_cmp(b)).unwrap());
+            bench_one(&mut group, "std_sort_unstable", n, &input,
+                      |x| x.sort_unstable_by(|a, b| a.total_cmp(b)));
+        }
+        group.finish();
+    }
+}
+
+// 2^20要素を小さな列に区切ってソートする。列ごとにfourth::sortを呼ぶ場合とbatchを比較する
+fn bench_batch(c: &mut Criterion) {
+    const TOTAL: usize = 1 << 20;
+    let mut group = c.benchmark_group("u32/segments");
+    group.sample_size(10);
+    group.warm_up_time(Duration::from_millis(500));
+    group.throughput(Throughput::Elements(TOTAL as u64));
+
+    let input: Vec<u32> = new_vec(TOTAL, Pattern::Random, SEED);
+    for &segment_len in &[32, 256, 1024] {
+        bench_one(&mut group, "batch", segment_len, &input,
+                  |x| batch::sort_segments(x, segment_len, &Ascending).unwrap());
+        bench_one(&mut group, "fourth_per_segment", segment_len, &input,
+                  |x| x.chunks_mut(segment_len).for_each(|s| fourth::sort(s, &Ascending).unwrap()));
+        bench_one(&mut group, "std_par_chunks", segment_len, &input,
+                  |x| x.par_chunks_mut(segment_len).for_each(|s| s.sort_unstable()));
+    }
+    group.finish();
+}
+
+// 入力をコピーしてから、そのコピーをsortでソートする時間を計測する
+fn bench_one<T, S>(group: &mut criterion::BenchmarkGroup<criterion::measurement::WallTime>,
+                   name: &str, n: usize, input: &[T], sort: S)
+    where T: Clone,
+          S: Fn(&mut [T])
+{
+    group.bench_with_input(BenchmarkId::new(name, n), input, |b, input| {
+        b.iter_batched_ref(|| input.to_vec(), |x| sort(x), BatchSize::LargeInput)
+    });
+}
+
+fn bench_u32(c: &mut Criterion) {
+    bench_sorters(c, "u32", |x: Vec<u32>| x);
+}
+
+fn bench_string(c: &mut Criterion) {
+    bench_sorters(c, "string", |x: Vec<String>| x);
+}
+
+fn bench_student(c: &mut Criterion) {
+    bench_sorters(c, "student", |x: Vec<u32>| x.into_iter().map(Student::from_u32).collect());
+}
+
+criterion_group!(benches, bench_u32, bench_simd, bench_f32, bench_string, bench_student, bench_batch);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+// ソーティングネットワークを書き出す
+//
+//   cargo run --example network -- <bitonic|small|odd_even> <n> <json|svg|text>
+
+use bitonic_sorter2::recorder::Network;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 3 {
+        eprintln!("usage: network <bitonic|small|odd_even> <n> <json|svg|text>");
+        process::exit(2);
+    }
+
+    let n: usize = match args[1].parse() {
+        Ok(n) => n,
+        Err(_) => {
+            eprintln!("invalid number of elements '{}'", args[1]);
+            process::exit(2);
+        }
+    };
+    let network = match args[0].as_str() {
+        "bitonic" => Network::bitonic(n),
+        "small" => Network::bitonic_with_small_networks(n),
+        "odd_even" => Network::odd_even(n),
+        other => {
+            eprintln!("unknown network '{}'", other);
+            process::exit(2);
+        }
+    };
+    match args[2].as_str() {
+        "json" => print!("{}", network.to_json()),
+        "svg" => print!("{}", network.to_svg()),
+        "text" => print!("{}", network.to_text()),
+        other => {
+            eprintln!("unknown format '{}'", other);
+            process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use super::{SortOrder, SortError};
+use super::recorder::Network;
+use super::utils::check_sorted_by;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+
+// 要素数の少ない多数の列をまとめてソートする
+// 例えば1024要素以下の列が何百万もあるとき、列ごとにfourth::sortを呼ぶと
+// 呼び出しやネットワークの分割のオーバーヘッドが大きくなる
+//
+// ソーティングネットワークは比較する要素の組が入力によらないので、
+// 同じ要素数の列はすべて同じ比較器の列でソートできる
+// そこで、比較器の列を一度だけ作り、GPUのように1つの比較器を一束の列すべてに
+// 順番に適用していく。束(バッチ)ごとにrayonで並列に処理する
+
+// 1つのバッチに含める要素数の目安。バッチ全体がキャッシュに収まる大きさにする
+const BATCH_ELEMENTS: usize = 4096;
+
+// xを先頭からsegment_len要素ずつに区切り、それぞれをorderの順にソートする
+// x.len()はsegment_lenの倍数であること
+pub fn sort_segments<T: Ord + Send>(x: &mut [T], segment_len: usize, order: &SortOrder)
+    -> Result<(), SortError>
+{
+    match *order {
+        SortOrder::Ascending => sort_segments_by(x, segment_len, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_segments_by(x, segment_len, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_segments_by<T, F>(x: &mut [T], segment_len: usize, comparator: &F)
+    -> Result<(), SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    if x.is_empty() {
+        return Ok(());
+    }
+    // usize::is_multiple_of(Rust 1.87)を使わず、古いコンパイラでもビルドできるようにする
+    #[allow(clippy::manual_is_multiple_of)]
+    if segment_len == 0 || x.len() % segment_len != 0 {
+        return Err(SortError::NotMultipleOfSegment(x.len(), segment_len));
+    }
+
+    let network = comparators(segment_len);
+    x.par_chunks_mut(batch_segments(segment_len) * segment_len).for_each(|batch| {
+        // 比較器ごとに、バッチ内のすべての列へ適用する
+        for &(i, j, swap_condition) in &network {
+            for segment in batch.chunks_exact_mut(segment_len) {
+                if comparator(&segment[i], &segment[j]) == swap_condition {
+                    segment.swap(i, j);
+                }
+            }
+        }
+    });
+    for segment in x.chunks(segment_len) {
+        check_sorted_by(segment, comparator);
+    }
+    Ok(())
+}
+
+// segmentsの各列を、それぞれorderの順にソートする
+// すべての列の要素数は等しいこと
+pub fn sort_slices<T: Ord + Send>(segments: &mut [&mut [T]], order: &SortOrder)
+    -> Result<(), SortError>
+{
+    match *order {
+        SortOrder::Ascending => sort_slices_by(segments, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_slices_by(segments, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_slices_by<T, F>(segments: &mut [&mut [T]], comparator: &F) -> Result<(), SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    let segment_len = match segments.first() {
+        Some(segment) => segment.len(),
+        None => return Ok(()),
+    };
+    if let Some(segment) = segments.iter().find(|segment| segment.len() != segment_len) {
+        return Err(SortError::UnequalSegments(segment_len, segment.len()));
+    }
+
+    let network = comparators(segment_len);
+    segments.par_chunks_mut(batch_segments(segment_len)).for_each(|batch| {
+        for &(i, j, swap_condition) in &network {
+            for segment in batch.iter_mut() {
+                if comparator(&segment[i], &segment[j]) == swap_condition {
+                    segment.swap(i, j);
+                }
+            }
+        }
+    });
+    for segment in segments.iter() {
+        check_sorted_by(segment, comparator);
+    }
+    Ok(())
+}
+
+// 1つのバッチに含める列の数
+fn batch_segments(segment_len: usize) -> usize {
+    (BATCH_ELEMENTS / segment_len).max(1)
+}
+
+// thirdやfourthと同じネットワークの比較器を、実行する順に並べて返す
+// 各比較器は、比較する2要素の位置と、要素を交換する比較結果の組で表す
+fn comparators(segment_len: usize) -> Vec<(usize, usize, Ordering)> {
+    Network::bitonic_with_small_networks(segment_len)
+        .comparators()
+        .map(|c| {
+            let swap_condition = match c.direction {
+                SortOrder::Ascending => Ordering::Greater,
+                SortOrder::Descending => Ordering::Less,
+            };
+            (c.i, c.j, swap_condition)
+        })
+        .collect()
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{sort_segments, sort_segments_by, sort_slices, sort_slices_by};
+    use crate::SortError;
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+
+    #[test]
+    fn sort_segments_u32() {
+        let mut x = vec![4, 3, 2, 1, 10, 30, 20, 40, 9, 8, 7, 6];
+        assert_eq!(sort_segments(&mut x, 4, &Ascending), Ok(()));
+        assert_eq!(x, vec![1, 2, 3, 4, 10, 20, 30, 40, 6, 7, 8, 9]);
+
+        assert_eq!(sort_segments(&mut x, 3, &Descending), Ok(()));
+        assert_eq!(x, vec![3, 2, 1, 20, 10, 4, 40, 30, 6, 9, 8, 7]);
+    }
+
+    #[test]
+    fn sort_segments_large() {
+        // バッチの境界をまたぐように、いろいろな要素数の列を多数ソートする
+        for &segment_len in &[1, 2, 17, 32, 100, 1024] {
+            let original = new_u32_vec(segment_len * 300);
+            let mut x = original.clone();
+            assert_eq!(sort_segments(&mut x, segment_len, &Descending), Ok(()));
+            for (segment, original) in x.chunks(segment_len).zip(original.chunks(segment_len)) {
+                let mut expected = original.to_vec();
+                expected.sort_by(|a, b| b.cmp(a));
+                assert_eq!(segment, &expected[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn sort_segments_by_key() {
+        let mut x = vec!["ccc", "a", "bb", "dddd", "ee", "f"];
+        assert_eq!(sort_segments_by(&mut x, 3, &|a: &&str, b: &&str| a.len().cmp(&b.len())), Ok(()));
+        assert_eq!(x, vec!["a", "bb", "ccc", "f", "ee", "dddd"]);
+    }
+
+    #[test]
+    fn sort_segments_errors() {
+        let mut x = new_u32_vec(10);
+        assert_eq!(sort_segments(&mut x, 4, &Ascending), Err(SortError::NotMultipleOfSegment(10, 4)));
+        assert_eq!(sort_segments(&mut x, 0, &Ascending), Err(SortError::NotMultipleOfSegment(10, 0)));
+
+        let mut x: Vec<u32> = Vec::new();
+        assert_eq!(sort_segments(&mut x, 0, &Ascending), Ok(()));
+    }
+
+    #[test]
+    fn sort_slices_u32() {
+        let mut vectors: Vec<Vec<u32>> = (0..1000).map(|_| new_u32_vec(64)).collect();
+        let mut slices: Vec<&mut [u32]> = vectors.iter_mut().map(|v| &mut v[..]).collect();
+        assert_eq!(sort_slices(&mut slices, &Ascending), Ok(()));
+        assert!(vectors.iter().all(|v| is_sorted_ascending(v)));
+
+        let mut slices: Vec<&mut [u32]> = vectors.iter_mut().map(|v| &mut v[..]).collect();
+        assert_eq!(sort_slices_by(&mut slices, &|a, b| b.cmp(a)), Ok(()));
+        assert!(vectors.iter().all(|v| is_sorted_descending(v)));
+    }
+
+    #[test]
+    fn sort_slices_unequal() {
+        let mut a = vec![3, 2, 1];
+        let mut b = vec![2, 1];
+        let mut slices: Vec<&mut [u32]> = vec![&mut a, &mut b];
+        assert_eq!(sort_slices(&mut slices, &Ascending), Err(SortError::UnequalSegments(3, 2)));
+
+        let mut slices: Vec<&mut [u32]> = Vec::new();
+        assert_eq!(sort_slices(&mut slices, &Ascending), Ok(()));
+    }
+}
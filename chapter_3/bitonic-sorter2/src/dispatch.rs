@@ -0,0 +1,254 @@
+use super::{SortOrder, SortError};
+use super::{third, fourth};
+use std::cmp::Ordering;
+
+// 要素の型に応じて、並列ソート(fourth)と順次ソート(third)を自動で選ぶ
+// 要素がSendならfourth、そうでなければthirdでソートする
+// RcやRefCellを持つ要素も、呼び出し側を書き換えずに同じ形でソートできる
+//
+//   let mut x = vec![10, 30, 11, 20];
+//   bitonic_sorter2::sort!(&mut x, &SortOrder::Ascending)?;
+//   bitonic_sorter2::sort_by!(&mut x, &|a: &u32, b: &u32| b.cmp(a))?;
+//
+// Rustの安定版には特殊化がないので、メソッド呼び出しの自動参照の優先順位を使って選ぶ
+// ParallelSortはソート対象の値そのものに、SequentialSortはその&mut参照に実装する
+// メソッドの探索では値の型が先に調べられるので、ParallelSortの境界を満たせばそちらが選ばれ、
+// 満たさなければ自動参照された&mut型のSequentialSortが選ばれる
+//
+// 選択に使うのは要素の型だけで、比較関数では選ばない
+// 同じ関数の中で定義したクロージャがSyncかどうかは、メソッドを選ぶ時点ではまだ決まらないので、
+// 比較関数で選ぼうとすると、呼び出し側の書き方によって結果が変わってしまう
+// そのため、sort_by!に渡す比較関数は、どちらのソートが選ばれるときもSyncであること
+// CellやRcを捕捉するSyncでない比較関数でソートするときは、third::sort_byを直接呼ぶ
+//
+// また、選択はマクロを展開した場所でわかっている境界だけを使って行われる
+// ジェネリックな関数の中では、要素の型引数にT: Sendを宣言していればfourth、
+// 宣言していなければ、呼び出し元がu32などで呼んでいてもthirdになる
+// 並列にソートしたい関数ではT: Sendを宣言するか、fourthを直接呼ぶ
+
+// sort!マクロのソート対象
+pub struct Sort<'a, 'b, T> {
+    x: &'a mut [T],
+    order: &'b SortOrder,
+}
+
+// sort_by!マクロのソート対象
+pub struct SortBy<'a, 'b, T, F> {
+    x: &'a mut [T],
+    comparator: &'b F,
+}
+
+impl<'a, 'b, T> Sort<'a, 'b, T> {
+    pub fn new(x: &'a mut [T], order: &'b SortOrder) -> Self {
+        Self { x, order }
+    }
+}
+
+impl<'a, 'b, T, F> SortBy<'a, 'b, T, F> {
+    pub fn new(x: &'a mut [T], comparator: &'b F) -> Self {
+        Self { x, comparator }
+    }
+}
+
+// 並列にソートできるときに選ばれる
+pub trait ParallelSort {
+    fn dispatch(self) -> Result<(), SortError>;
+}
+
+// 並列にソートできないときに選ばれる
+pub trait SequentialSort {
+    fn dispatch(self) -> Result<(), SortError>;
+}
+
+impl<'a, 'b, T: Ord + Send> ParallelSort for Sort<'a, 'b, T> {
+    fn dispatch(self) -> Result<(), SortError> {
+        fourth::sort(self.x, self.order)
+    }
+}
+
+impl<'a, 'b, T: Ord> SequentialSort for &mut Sort<'a, 'b, T> {
+    fn dispatch(self) -> Result<(), SortError> {
+        third::sort(self.x, self.order)
+    }
+}
+
+impl<'a, 'b, T, F> ParallelSort for SortBy<'a, 'b, T, F>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    fn dispatch(self) -> Result<(), SortError> {
+        fourth::sort_by(self.x, self.comparator)
+    }
+}
+
+// 比較関数で選ばないように、こちらでもSyncを要求する
+impl<'a, 'b, T, F> SequentialSort for &mut SortBy<'a, 'b, T, F>
+    where F: Sync + Fn(&T, &T) -> Ordering
+{
+    fn dispatch(self) -> Result<(), SortError> {
+        third::sort_by(self.x, self.comparator)
+    }
+}
+
+// xをorderの順にソートする。要素がSendならfourth、そうでなければthirdを使う
+#[macro_export]
+macro_rules! sort {
+    ($x:expr, $order:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::dispatch::{ParallelSort, SequentialSort};
+        $crate::dispatch::Sort::new($x, $order).dispatch()
+    }};
+}
+
+// xをcomparatorの順にソートする。要素がSendならfourth、そうでなければthirdを使う
+// comparatorはSyncであること(モジュールの先頭を参照)
+#[macro_export]
+macro_rules! sort_by {
+    ($x:expr, $comparator:expr) => {{
+        #[allow(unused_imports)]
+        use $crate::dispatch::{ParallelSort, SequentialSort};
+        $crate::dispatch::SortBy::new($x, $comparator).dispatch()
+    }};
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use crate::SortError;
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Rcを持つのでSendではない構造体
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    struct Record {
+        id: u32,
+        tags: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Record {
+        fn new(id: u32) -> Self {
+            Self { id, tags: Rc::new(RefCell::new(Vec::new())) }
+        }
+    }
+
+    // 比較がrayonのワーカースレッド(呼び出したスレッド以外)で行われたら記録する要素
+    struct OnWorker<'a>(u32, &'a AtomicBool);
+
+    impl<'a> OnWorker<'a> {
+        fn record(&self) {
+            if rayon::current_thread_index().is_some() {
+                self.1.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    impl<'a> PartialEq for OnWorker<'a> {
+        fn eq(&self, other: &Self) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    impl<'a> Eq for OnWorker<'a> {}
+
+    impl<'a> PartialOrd for OnWorker<'a> {
+        fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            Some(self.cmp(other))
+        }
+    }
+
+    impl<'a> Ord for OnWorker<'a> {
+        fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            self.record();
+            self.0.cmp(&other.0)
+        }
+    }
+
+    #[test]
+    fn sort_send_values_in_parallel() {
+        // しきい値を超える要素数なら、比較の一部はrayonのワーカースレッドで行われる
+        let on_worker = AtomicBool::new(false);
+        let mut x = new_u32_vec(65536);
+        let comparator = |a: &u32, b: &u32| {
+            if rayon::current_thread_index().is_some() {
+                on_worker.store(true, Ordering::Relaxed);
+            }
+            a.cmp(b)
+        };
+        assert_eq!(sort_by!(&mut x, &comparator), Ok(()));
+        assert!(is_sorted_ascending(&x));
+        assert!(on_worker.load(Ordering::Relaxed));
+
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort!(&mut x, &Descending), Ok(()));
+        assert!(is_sorted_descending(&x));
+    }
+
+    #[test]
+    fn sort_non_send_values_sequentially() {
+        let mut x: Vec<Record> = [16, 14, 17, 15].iter().map(|&id| Record::new(id)).collect();
+        x[0].tags.borrow_mut().push("first".to_string());
+        assert_eq!(sort!(&mut x, &Ascending), Ok(()));
+        let ids: Vec<_> = x.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![14, 15, 16, 17]);
+        assert_eq!(*x[2].tags.borrow(), vec!["first".to_string()]);
+
+        assert_eq!(sort_by!(&mut x, &|a: &Record, b: &Record| b.id.cmp(&a.id)), Ok(()));
+        let ids: Vec<_> = x.iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec![17, 16, 15, 14]);
+    }
+
+    #[test]
+    fn sort_non_send_values_with_capturing_comparator() {
+        // sort_send_values_in_parallelと同じ形の呼び出しでも、要素がSendでなければ
+        // 呼び出したスレッドだけで比較する
+        let on_worker = AtomicBool::new(false);
+        let mut x: Vec<Record> = new_u32_vec(65536).into_iter().map(Record::new).collect();
+        let comparator = |a: &Record, b: &Record| {
+            if rayon::current_thread_index().is_some() {
+                on_worker.store(true, Ordering::Relaxed);
+            }
+            a.id.cmp(&b.id)
+        };
+        assert_eq!(sort_by!(&mut x, &comparator), Ok(()));
+        assert!(x.windows(2).all(|w| w[0].id <= w[1].id));
+        assert!(!on_worker.load(Ordering::Relaxed));
+    }
+
+    // 要素の型引数にSendの境界を宣言した関数
+    fn sort_send<T: Ord + Send>(x: &mut [T]) -> Result<(), SortError> {
+        sort!(x, &Ascending)
+    }
+
+    // Sendの境界を宣言していない関数
+    fn sort_any<T: Ord>(x: &mut [T]) -> Result<(), SortError> {
+        sort!(x, &Ascending)
+    }
+
+    #[test]
+    fn generic_functions_use_declared_bounds() {
+        // 同じ型の列でも、選ばれるソートは関数が宣言した境界で決まる
+        let on_worker = AtomicBool::new(false);
+        let x = new_u32_vec(65536);
+        let mut y: Vec<_> = x.iter().map(|&v| OnWorker(v, &on_worker)).collect();
+        assert_eq!(sort_any(&mut y), Ok(()));
+        assert!(y.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert!(!on_worker.load(Ordering::Relaxed));
+
+        let mut y: Vec<_> = x.iter().map(|&v| OnWorker(v, &on_worker)).collect();
+        assert_eq!(sort_send(&mut y), Ok(()));
+        assert!(y.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert!(on_worker.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn sort_not_power_of_two() {
+        let mut x = vec![Record::new(1), Record::new(2), Record::new(3)];
+        assert_eq!(sort!(&mut x, &Ascending), Err(SortError::NotPowerOfTwo(3)));
+
+        let mut x = vec![10u32, 30, 11];
+        assert_eq!(sort!(&mut x, &Ascending), Err(SortError::NotPowerOfTwo(3)));
+    }
+}
@@ -0,0 +1,447 @@
+use super::{SortOrder, fourth};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+// メモリに収まらない大きさのファイルをソートする(外部ソート)
+// 1. 入力ファイルをメモリ予算に収まる大きさのチャンクに分けて読み込み、
+//    それぞれをfourth::sort_by_arbitraryで並列にソートして一時ファイル(ラン)に書き出す
+// 2. 一度に開くランの数をfan_in個までに抑えてk-wayマージする
+//    ランがfan_in個より多いときは、fan_in個ずつマージした中間のランを作ることを繰り返す
+
+// レコードのバイトオーダー
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+// ファイルに固定長のバイト列で書かれたレコード
+// u32, u64, i32, i64には実装済み。独自のレイアウトのレコードはこのトレイトを実装する
+pub trait Record: Sized + Send {
+    // 1レコードのバイト数
+    const SIZE: usize;
+    // SIZEバイトのバイト列からレコードを作る
+    fn decode(bytes: &[u8], endian: Endian) -> Self;
+    // レコードをSIZEバイトのバイト列に書き込む
+    fn encode(&self, bytes: &mut [u8], endian: Endian);
+}
+
+// 整数型にRecordを実装するマクロ
+macro_rules! impl_record_for_int {
+    ($($t:ty),*) => {
+        $(
+            impl Record for $t {
+                const SIZE: usize = mem::size_of::<$t>();
+
+                fn decode(bytes: &[u8], endian: Endian) -> Self {
+                    let mut buf = [0; mem::size_of::<$t>()];
+                    buf.copy_from_slice(bytes);
+                    match endian {
+                        Endian::Little => <$t>::from_le_bytes(buf),
+                        Endian::Big => <$t>::from_be_bytes(buf),
+                    }
+                }
+
+                fn encode(&self, bytes: &mut [u8], endian: Endian) {
+                    let buf = match endian {
+                        Endian::Little => self.to_le_bytes(),
+                        Endian::Big => self.to_be_bytes(),
+                    };
+                    bytes.copy_from_slice(&buf);
+                }
+            }
+        )*
+    };
+}
+
+impl_record_for_int!(u32, u64, i32, i64);
+
+// 既定のメモリ予算(256MiB)
+const DEFAULT_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+// 一度にマージするランの既定の数
+const DEFAULT_FAN_IN: usize = 64;
+
+// 外部ソートの設定
+// ExternalSortConfig::new()に続けてメソッドを呼び、必要な項目だけを変更する
+pub struct ExternalSortConfig {
+    // 1つのチャンクをソートするときに使うメモリのバイト数
+    memory_budget: usize,
+    // レコードのバイトオーダー
+    endian: Endian,
+    // ランを書き出すディレクトリ。Noneならシステムの一時ディレクトリを使う
+    temp_dir: Option<PathBuf>,
+    // 一度にマージするランの数の上限
+    fan_in: usize,
+}
+
+impl ExternalSortConfig {
+    pub fn new() -> Self {
+        Self {
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            endian: Endian::Little,
+            temp_dir: None,
+            fan_in: DEFAULT_FAN_IN,
+        }
+    }
+
+    // メモリ予算をバイト数で設定する
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    // レコードのバイトオーダーを設定する
+    pub fn endian(mut self, endian: Endian) -> Self {
+        self.endian = endian;
+        self
+    }
+
+    // ランを書き出すディレクトリを設定する
+    pub fn temp_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.temp_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    // 一度にマージするランの数の上限を設定する。同時に開くファイルの数もこれで抑えられる
+    // 2未満を指定したときは2とする
+    pub fn fan_in(mut self, runs: usize) -> Self {
+        self.fan_in = runs.max(2);
+        self
+    }
+
+    // 1つのチャンクに読み込むレコード数。少なくとも1レコードは読み込む
+    fn records_per_chunk<T: Record>(&self) -> usize {
+        let record_bytes = T::SIZE.max(mem::size_of::<T>()).max(1);
+        (self.memory_budget / record_bytes).max(1)
+    }
+
+    fn create_temp_file(&self) -> io::Result<File> {
+        match self.temp_dir {
+            Some(ref dir) => tempfile::tempfile_in(dir),
+            None => tempfile::tempfile(),
+        }
+    }
+}
+
+impl Default for ExternalSortConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// inputのレコードをorderの順にソートしてoutputに書き出す
+// ソートしたレコード数を返す
+pub fn sort_file<T, P, Q>(input: P, output: Q, order: &SortOrder, config: &ExternalSortConfig)
+    -> io::Result<u64>
+    where T: Record + Ord,
+          P: AsRef<Path>,
+          Q: AsRef<Path>
+{
+    let forward = match *order {
+        SortOrder::Ascending => true,
+        SortOrder::Descending => false,
+    };
+    let input = File::open(input)?;
+    // 読み込むチャンクの領域を、ファイルに残っているレコード数より多く確保しないようにする
+    let mut remaining = input.metadata()?.len() / T::SIZE.max(1) as u64;
+    let mut reader = BufReader::new(input);
+
+    // 1. チャンクごとにソートしてランを作る
+    let mut runs = Vec::new();
+    let mut count = 0;
+    loop {
+        let mut chunk: Vec<T> = read_chunk(&mut reader, remaining, config)?;
+        if chunk.is_empty() {
+            break;
+        }
+        count += chunk.len() as u64;
+        remaining = remaining.saturating_sub(chunk.len() as u64);
+        sort_chunk(&mut chunk, forward);
+
+        // 入力全体が1つのチャンクに収まったら、一時ファイルを使わずに出力する
+        if runs.is_empty() && chunk.len() < config.records_per_chunk::<T>() {
+            let mut writer = BufWriter::new(File::create(output)?);
+            write_records(&mut writer, &chunk, config.endian)?;
+            writer.flush()?;
+            return Ok(count);
+        }
+
+        let mut run = BufWriter::new(config.create_temp_file()?);
+        write_records(&mut run, &chunk, config.endian)?;
+        let mut run = run.into_inner().map_err(|e| e.into_error())?;
+        run.seek(SeekFrom::Start(0))?;
+        runs.push(run);
+    }
+
+    // 2. ランがfan_in個以下になるまで中間のランにまとめてから、出力ファイルへマージする
+    while runs.len() > config.fan_in {
+        runs = merge_pass::<T>(runs, forward, config)?;
+    }
+    let mut writer = BufWriter::new(File::create(output)?);
+    merge_runs::<T, _>(runs, &mut writer, forward, config)?;
+    writer.flush()?;
+    Ok(count)
+}
+
+fn sort_chunk<T: Record + Ord>(chunk: &mut [T], forward: bool) {
+    // チャンクの要素数は2のべき乗とは限らないので、要素数に制約のないモードを使う
+    let result = if forward {
+        fourth::sort_by_arbitrary(chunk, &|a, b| a.cmp(b))
+    } else {
+        fourth::sort_by_arbitrary(chunk, &|a, b| b.cmp(a))
+    };
+    result.expect("sort_by_arbitrary accepts any length");
+}
+
+// 最大でrecords_per_chunk個のレコードを読み込む
+// remainingはファイルに残っているレコード数の見込みで、確保する領域の大きさだけに使う
+fn read_chunk<T: Record, R: Read>(reader: &mut R, remaining: u64, config: &ExternalSortConfig)
+    -> io::Result<Vec<T>>
+{
+    let limit = config.records_per_chunk::<T>();
+    let capacity = if remaining < limit as u64 { remaining as usize } else { limit };
+    let mut chunk = Vec::with_capacity(capacity);
+    let mut buf = vec![0; T::SIZE];
+    while chunk.len() < limit {
+        match read_record(reader, &mut buf, config.endian)? {
+            Some(record) => chunk.push(record),
+            None => break,
+        }
+    }
+    Ok(chunk)
+}
+
+// bufを使ってレコードを1つ読み込む。ファイルの終わりならNoneを返す
+// ファイルの長さがレコードの大きさの倍数でないときはエラーにする
+fn read_record<T: Record, R: Read>(reader: &mut R, buf: &mut [u8], endian: Endian)
+    -> io::Result<Option<T>>
+{
+    let mut filled = 0;
+    while filled < T::SIZE {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                               "the file ends in the middle of a record")),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(T::decode(buf, endian)))
+}
+
+fn write_records<T: Record, W: Write>(writer: &mut W, records: &[T], endian: Endian) -> io::Result<()> {
+    let mut buf = vec![0; T::SIZE];
+    for record in records {
+        record.encode(&mut buf, endian);
+        writer.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+// k-wayマージで使うヒープの要素。各ランの先頭のレコードを持つ
+struct HeapEntry<T> {
+    record: T,
+    run: usize,
+    forward: bool,
+}
+
+// BinaryHeapは最大値から取り出すので、先に出力すべきレコードを大きいものとして扱う
+impl<T: Ord> Ord for HeapEntry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let order = if self.forward {
+            other.record.cmp(&self.record)
+        } else {
+            self.record.cmp(&other.record)
+        };
+        // 等しいレコードは番号の小さいランから取り出す
+        order.then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl<T: Ord> PartialOrd for HeapEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Ord> PartialEq for HeapEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for HeapEntry<T> {}
+
+// ランをfan_in個ずつk-wayマージして一時ファイルに書き出し、できた中間のランを返す
+fn merge_pass<T>(runs: Vec<File>, forward: bool, config: &ExternalSortConfig) -> io::Result<Vec<File>>
+    where T: Record + Ord
+{
+    let mut merged = Vec::new();
+    let mut runs = runs.into_iter().peekable();
+    while runs.peek().is_some() {
+        let group: Vec<File> = runs.by_ref().take(config.fan_in).collect();
+        let mut run = BufWriter::new(config.create_temp_file()?);
+        merge_runs::<T, _>(group, &mut run, forward, config)?;
+        let mut run = run.into_inner().map_err(|e| e.into_error())?;
+        run.seek(SeekFrom::Start(0))?;
+        merged.push(run);
+    }
+    Ok(merged)
+}
+
+fn merge_runs<T, W>(runs: Vec<File>, writer: &mut W, forward: bool, config: &ExternalSortConfig)
+    -> io::Result<()>
+    where T: Record + Ord,
+          W: Write
+{
+    // メモリ予算をランの読み込みバッファに分配する
+    let buffer_size = (config.memory_budget / (runs.len() + 1)).max(8 * 1024);
+    let mut readers: Vec<_> = runs.into_iter()
+        .map(|run| BufReader::with_capacity(buffer_size, run))
+        .collect();
+
+    let mut buf = vec![0; T::SIZE];
+    let mut heap: BinaryHeap<HeapEntry<T>> = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some(record) = read_record(reader, &mut buf, config.endian)? {
+            heap.push(HeapEntry { record, run, forward });
+        }
+    }
+
+    while let Some(HeapEntry { record, run, .. }) = heap.pop() {
+        record.encode(&mut buf, config.endian);
+        writer.write_all(&buf)?;
+        // 取り出したレコードと同じランから、次のレコードを補充する
+        if let Some(record) = read_record(&mut readers[run], &mut buf, config.endian)? {
+            heap.push(HeapEntry { record, run, forward });
+        }
+    }
+    Ok(())
+}
+
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{read_chunk, sort_file, Endian, ExternalSortConfig, Record};
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, new_vec, Pattern};
+    use std::fs;
+    use std::io;
+    use std::path::Path;
+
+    fn write_file<T: Record>(path: &Path, records: &[T], endian: Endian) {
+        let mut bytes = vec![0; records.len() * T::SIZE];
+        for (record, chunk) in records.iter().zip(bytes.chunks_mut(T::SIZE)) {
+            record.encode(chunk, endian);
+        }
+        fs::write(path, bytes).unwrap();
+    }
+
+    fn read_file<T: Record>(path: &Path, endian: Endian) -> Vec<T> {
+        fs::read(path).unwrap().chunks(T::SIZE).map(|chunk| T::decode(chunk, endian)).collect()
+    }
+
+    #[test]
+    fn sort_u32_file_in_many_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+
+        let records = new_u32_vec(100_000);
+        write_file(&input, &records, Endian::Little);
+
+        // 1つのチャンクを4096バイト(1024レコード)にして、約100個のランを作る
+        let config = ExternalSortConfig::new().memory_budget(4096).temp_dir(dir.path());
+        assert_eq!(sort_file::<u32, _, _>(&input, &output, &Ascending, &config).unwrap(), 100_000);
+
+        let mut expected = records.clone();
+        expected.sort();
+        assert_eq!(read_file::<u32>(&output, Endian::Little), expected);
+    }
+
+    #[test]
+    fn merge_in_bounded_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+
+        let records: Vec<u64> = new_vec(30_000, Pattern::Random, 2);
+        write_file(&input, &records, Endian::Little);
+
+        // 約60個のランを3個ずつマージするので、中間のランを作るパスが何度も必要になる
+        let config = ExternalSortConfig::new().memory_budget(4096).fan_in(3).temp_dir(dir.path());
+        for order in &[Ascending, Descending] {
+            assert_eq!(sort_file::<u64, _, _>(&input, &output, order, &config).unwrap(), 30_000);
+
+            let mut expected = records.clone();
+            match *order {
+                Ascending => expected.sort(),
+                Descending => expected.sort_by(|a, b| b.cmp(a)),
+            }
+            assert_eq!(read_file::<u64>(&output, Endian::Little), expected);
+        }
+    }
+
+    #[test]
+    fn chunk_capacity_is_capped_by_remaining_records() {
+        // 既定のメモリ予算は256MiBだが、3レコードの読み込みでその分の領域を確保しない
+        let bytes = [1u8, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0];
+        let chunk: Vec<u32> = read_chunk(&mut &bytes[..], 3, &ExternalSortConfig::new()).unwrap();
+        assert_eq!(chunk, vec![1, 2, 3]);
+        assert!(chunk.capacity() < 1024);
+    }
+
+    #[test]
+    fn sort_u64_file_big_endian_descending() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+
+        let records: Vec<u64> = new_vec(50_000, Pattern::FewUnique { unique: 100 }, 1);
+        write_file(&input, &records, Endian::Big);
+
+        let config = ExternalSortConfig::new().memory_budget(64 * 1024).endian(Endian::Big);
+        assert_eq!(sort_file::<u64, _, _>(&input, &output, &Descending, &config).unwrap(), 50_000);
+
+        let mut expected = records.clone();
+        expected.sort_by(|a, b| b.cmp(a));
+        assert_eq!(read_file::<u64>(&output, Endian::Big), expected);
+    }
+
+    #[test]
+    fn sort_small_and_empty_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+
+        // 1つのチャンクに収まる入力
+        write_file(&input, &[3i32, -1, 2], Endian::Little);
+        let config = ExternalSortConfig::new();
+        assert_eq!(sort_file::<i32, _, _>(&input, &output, &Ascending, &config).unwrap(), 3);
+        assert_eq!(read_file::<i32>(&output, Endian::Little), vec![-1, 2, 3]);
+
+        write_file::<i64>(&input, &[], Endian::Little);
+        assert_eq!(sort_file::<i64, _, _>(&input, &output, &Ascending, &config).unwrap(), 0);
+        assert_eq!(fs::read(&output).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.bin");
+        let output = dir.path().join("output.bin");
+
+        // u32のレコード2つと、余分な2バイト
+        fs::write(&input, [1, 0, 0, 0, 2, 0, 0, 0, 9, 9]).unwrap();
+        let err = sort_file::<u32, _, _>(&input, &output, &Ascending, &ExternalSortConfig::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,152 @@
+use super::{SortOrder, SortError, third, fourth};
+use std::cmp::Ordering;
+
+// f32やf64はOrdを実装していないので、そのままではsortに渡せない
+// このモジュールではIEEE 754のtotalOrderで比較してソートする
+// totalOrderでは-0.0 < +0.0となり、NaNも含めてすべての値の順序が決まる
+
+// NaNを置く位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NanPlacement {
+    // 昇順でも降順でも、NaNを先頭にまとめる
+    First,
+    // 昇順でも降順でも、NaNを末尾にまとめる
+    Last,
+}
+
+// totalOrderで比較できる浮動小数点数の型
+pub trait TotalOrder: Copy {
+    fn total_cmp(&self, other: &Self) -> Ordering;
+    fn is_nan(&self) -> bool;
+}
+
+impl TotalOrder for f32 {
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+
+    fn is_nan(&self) -> bool {
+        f32::is_nan(*self)
+    }
+}
+
+impl TotalOrder for f64 {
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f64::total_cmp(self, other)
+    }
+
+    fn is_nan(&self) -> bool {
+        f64::is_nan(*self)
+    }
+}
+
+// orderの順に並べ、NaNをnansの位置にまとめる比較関数を返す
+// sort_byやsort_by_arbitraryなど、比較関数を受け取るソートにそのまま渡せる
+pub fn comparator<T: TotalOrder>(order: &SortOrder, nans: NanPlacement)
+    -> impl Fn(&T, &T) -> Ordering + Send + Sync
+{
+    let forward = match *order {
+        SortOrder::Ascending => true,
+        SortOrder::Descending => false,
+    };
+    // NaNを先頭に置くなら、NaNは他のどの値よりも小さいものとして扱う
+    let nan_vs_number = match nans {
+        NanPlacement::First => Ordering::Less,
+        NanPlacement::Last => Ordering::Greater,
+    };
+
+    move |a: &T, b: &T| match (a.is_nan(), b.is_nan()) {
+        (true, false) => nan_vs_number,
+        (false, true) => nan_vs_number.reverse(),
+        // 数同士とNaN同士はtotalOrderで比べ、降順なら結果を反転する
+        _ if forward => a.total_cmp(b),
+        _ => b.total_cmp(a),
+    }
+}
+
+// thirdのsort_byを使って順番にソートする
+pub fn sort<T: TotalOrder>(x: &mut [T], order: &SortOrder, nans: NanPlacement)
+    -> Result<(), SortError>
+{
+    third::sort_by(x, &comparator(order, nans))
+}
+
+// fourthのsort_byを使って並列にソートする
+pub fn par_sort<T: TotalOrder + Send>(x: &mut [T], order: &SortOrder, nans: NanPlacement)
+    -> Result<(), SortError>
+{
+    fourth::sort_by(x, &comparator(order, nans))
+}
+
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{sort, par_sort, comparator, NanPlacement};
+    use crate::SortError;
+    use crate::SortOrder::*;
+    use crate::third;
+    use crate::utils::{new_vec, Pattern};
+
+    // 結果を比較しやすいように、ビットパターンの列に変換する
+    fn bits(x: &[f64]) -> Vec<u64> {
+        x.iter().map(|v| v.to_bits()).collect()
+    }
+
+    fn input() -> Vec<f64> {
+        vec![1.5, f64::NAN, -0.0, f64::NEG_INFINITY, 0.0, -f64::NAN, f64::INFINITY, -2.0]
+    }
+
+    #[test]
+    fn sort_f64_ascending() {
+        let mut x = input();
+        assert_eq!(sort(&mut x, &Ascending, NanPlacement::Last), Ok(()));
+        // NaN同士はtotalOrderで比べるので、負のNaNが先になる
+        assert_eq!(bits(&x), bits(&[f64::NEG_INFINITY, -2.0, -0.0, 0.0, 1.5, f64::INFINITY,
+                                    -f64::NAN, f64::NAN]));
+
+        let mut x = input();
+        assert_eq!(sort(&mut x, &Ascending, NanPlacement::First), Ok(()));
+        assert_eq!(bits(&x), bits(&[-f64::NAN, f64::NAN, f64::NEG_INFINITY, -2.0, -0.0, 0.0,
+                                    1.5, f64::INFINITY]));
+    }
+
+    #[test]
+    fn sort_f64_descending() {
+        let mut x = input();
+        assert_eq!(par_sort(&mut x, &Descending, NanPlacement::Last), Ok(()));
+        assert_eq!(bits(&x), bits(&[f64::INFINITY, 1.5, 0.0, -0.0, -2.0, f64::NEG_INFINITY,
+                                    f64::NAN, -f64::NAN]));
+
+        let mut x = input();
+        assert_eq!(par_sort(&mut x, &Descending, NanPlacement::First), Ok(()));
+        assert_eq!(bits(&x), bits(&[f64::NAN, -f64::NAN, f64::INFINITY, 1.5, 0.0, -0.0, -2.0,
+                                    f64::NEG_INFINITY]));
+    }
+
+    #[test]
+    fn sort_f32_large() {
+        let mut x: Vec<f32> = new_vec::<f64>(65536, Pattern::Random, 1)
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| if i % 100 == 0 { f32::NAN } else { v as f32 - 0.5 })
+            .collect();
+        let mut expected = x.clone();
+        expected.sort_by(|a, b| a.total_cmp(b));
+
+        assert_eq!(par_sort(&mut x, &Ascending, NanPlacement::Last), Ok(()));
+        assert_eq!(x.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                   expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn comparator_with_arbitrary_length() {
+        // comparatorは要素数の制約がないsort_by_arbitraryにも渡せる
+        let mut x = vec![3.0, f64::NAN, 1.0];
+        assert_eq!(sort(&mut x, &Ascending, NanPlacement::First), Err(SortError::NotPowerOfTwo(3)));
+        assert_eq!(third::sort_by_arbitrary(&mut x, &comparator(&Ascending, NanPlacement::First)),
+                   Ok(()));
+        assert!(x[0].is_nan());
+        assert_eq!(&x[1..], &[1.0, 3.0]);
+    }
+}
@@ -1,67 +1,312 @@
-use super::SortOrder;
+use super::{SortOrder, SortError, apply_permutation, greatest_power_of_two_less_than, no_values};
+use super::networks;
+use super::utils::check_sorted_by;
 use rayon;
+use rayon::prelude::*;
 use std::cmp::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
 
-pub fn sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+pub fn sort<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
     // do_sortを呼ぶ代わりに、sort_byを呼ぶようにする
-    // is_power_of_twoはsort_byが呼ぶだめ、ここからは削除
-    if x.len().is_power_of_two() {
-        match *order {
-            SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
-            SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
-        };
-        Ok(())
-    } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
+    // is_power_of_twoはsort_byが呼ぶため、ここからは削除
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+// 要素数が2のべき乗でなくてもソートできるモード
+pub fn sort_arbitrary<T: Ord + Send>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by_arbitrary(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by_arbitrary(x, &|a, b| b.cmp(a)),
     }
 }
 
 // 並列に処理するか決めるしきい値
 const PARALLEL_THRESHOLD: usize = 4096;
 
-fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+// 並列ソートの設定
+// SortConfig::new()に続けてメソッドを呼び、必要な項目だけを変更する
+pub struct SortConfig<'a> {
+    // 分割後の要素数がこの値以上なら並列に処理する
+    threshold: usize,
+    // rayon::joinを入れ子にできる最大の深さ。Noneなら制限しない
+    max_depth: Option<usize>,
+    // ソートに使うスレッドプール。Noneならrayonのグローバルプールを使う
+    pool: Option<&'a rayon::ThreadPool>,
+    // 統計を数えるカウンタ。sort_by_with_statsのときだけ設定される
+    stats: Option<&'a StatsCounters>,
+    // 中断の要求と進捗の通知先。sort_by_with_progressのときだけ設定される
+    cancel: Option<&'a Cancel<'a>>,
+    progress: Option<&'a Progress<'a>>,
+}
+
+impl<'a> SortConfig<'a> {
+    pub fn new() -> Self {
+        Self {
+            threshold: PARALLEL_THRESHOLD,
+            max_depth: None,
+            pool: None,
+            stats: None,
+            cancel: None,
+            progress: None,
+        }
+    }
+
+    // 並列に処理するか決めるしきい値を設定する
+    pub fn threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    // rayon::joinの入れ子の深さを制限する。0なら並列処理を行わない
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    // ソートを指定したスレッドプールで実行する
+    // 使用するスレッド数はプールのスレッド数までに抑えられる
+    pub fn thread_pool(mut self, pool: &'a rayon::ThreadPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // 分割後の要素数がlenで、rayon::joinの深さがdepthのときに並列に処理するか
+    fn should_split(&self, len: usize, depth: usize) -> bool {
+        let below_max_depth = match self.max_depth {
+            Some(max_depth) => depth < max_depth,
+            None => true,
+        };
+        len >= self.threshold && below_max_depth
+    }
+
+    // 統計を数えるときは、compare_and_swapが交換した回数を加える
+    fn record_swaps(&self, swaps: usize) {
+        if let Some(stats) = self.stats {
+            stats.swaps.fetch_add(swaps, Relaxed);
+        }
+    }
+
+    // 統計を数えるときは、再帰の深さとrayon::joinで分割したことを記録する
+    fn record_call(&self, level: usize, joined: bool) {
+        if let Some(stats) = self.stats {
+            stats.max_recursion_depth.fetch_max(level, Relaxed);
+            if joined {
+                stats.joins.fetch_add(1, Relaxed);
+            }
+        }
+    }
+
+    // ソートの中断が要求されたか
+    fn is_cancelled(&self) -> bool {
+        match self.cancel {
+            Some(cancel) => cancel.requested.load(Relaxed),
+            None => false,
+        }
+    }
+
+    // 中断が要求されていたら、処理を省いたことを記録してtrueを返す
+    // do_sortとsub_sortは、これがtrueなら何もせずに返る
+    fn skip_if_cancelled(&self) -> bool {
+        match self.cancel {
+            Some(cancel) if cancel.requested.load(Relaxed) => {
+                cancel.skipped.store(true, Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // 進捗を通知するときは、処理を終えた仕事量workを加えて通知する
+    // 中断された後は、処理していない部分があるので通知しない
+    fn record_progress(&self, work: usize) {
+        if let Some(progress) = self.progress {
+            if !self.is_cancelled() {
+                let done = progress.done.fetch_add(work, Relaxed) + work;
+                (progress.callback)(done, progress.total);
+            }
+        }
+    }
+
+    // 設定されたスレッドプール上でfを実行する
+    fn run<R, F>(&self, f: F) -> R
+        where R: Send,
+              F: Send + FnOnce() -> R
+    {
+        match self.pool {
+            Some(pool) => pool.install(f),
+            None => f(),
+        }
+    }
+}
+
+impl<'a> Default for SortConfig<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// sort_by_with_statsが返す、ソート中の統計
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SortStats {
+    // comparatorを呼んだ回数
+    pub comparisons: usize,
+    // 要素を交換した回数
+    pub swaps: usize,
+    // do_sortとsub_sortの再帰呼び出しの最大の深さ。最初の呼び出しを0とする
+    pub max_recursion_depth: usize,
+    // rayon::joinで並列に処理した回数
+    pub joins: usize,
+}
+
+// 複数のスレッドから統計を数えるためのカウンタ
+#[derive(Default)]
+struct StatsCounters {
+    comparisons: AtomicUsize,
+    swaps: AtomicUsize,
+    max_recursion_depth: AtomicUsize,
+    joins: AtomicUsize,
+}
+
+impl StatsCounters {
+    fn to_stats(&self) -> SortStats {
+        SortStats {
+            comparisons: self.comparisons.load(Relaxed),
+            swaps: self.swaps.load(Relaxed),
+            max_recursion_depth: self.max_recursion_depth.load(Relaxed),
+            joins: self.joins.load(Relaxed),
+        }
+    }
+}
+
+// 進捗を通知する要素数の単位
+// これより大きな部分列ではcompare_and_swapのたびに、この要素数の部分列ではソートやマージを
+// 終えるたびに通知する。小さな部分列ごとに通知すると、コールバックの呼び出しが多くなりすぎる
+const PROGRESS_CHUNK: usize = 16384;
+
+// sort_by_with_progressの中断の要求と、中断によって実際に処理を省いたか
+// すべての処理を終えた後に要求されたときは、ソートは完了しているので中断とはみなさない
+struct Cancel<'a> {
+    requested: &'a AtomicBool,
+    skipped: AtomicBool,
+}
+
+// sort_by_with_progressの進捗
+// 仕事量は、compare_and_swapと固定長のネットワークが受け持った要素数の合計で表す
+struct Progress<'a> {
+    done: AtomicUsize,
+    total: usize,
+    callback: &'a (dyn Fn(usize, usize) + Sync),
+}
+
+// 要素数lenのdo_sortの仕事量。lenは2のべき乗であること
+fn do_sort_work(len: usize) -> usize {
+    if len <= networks::MAX_LEN {
+        len
+    } else {
+        2 * do_sort_work(len / 2) + sub_sort_work(len)
+    }
+}
+
+// 要素数lenのsub_sortの仕事量。lenは2のべき乗であること
+// log2(len)段のcompare_and_swapが、それぞれlen要素を受け持つ
+fn sub_sort_work(len: usize) -> usize {
+    len * len.trailing_zeros() as usize
+}
+
+// xをソートする。xの要素を入れ替えるときは、valuesの同じ位置の要素も一緒に入れ替える
+// キーだけをソートするときは、valuesにno_valuesで作った()の列を渡す
+// depthはrayon::joinの入れ子の深さ、levelは再帰呼び出しの深さ
+pub(crate) fn do_sort<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F,
+                               config: &SortConfig, depth: usize, level: usize)
     where T: Send,
+          V: Send,
           F: Sync + Fn(&T, &T) -> Ordering
 {    
-    if x.len() > 1 {
+    // 中断が要求されていたら、ここから先の処理を行わない
+    // 要素は交換しかしていないので、xは元の列を並べ替えたものになっている
+    if config.skip_if_cancelled() {
+        return;
+    }
+    if x.len() <= networks::MAX_LEN {
+        // 小さな部分列は、比較器の少ない固定長のネットワークでソートする
+        let swaps = networks::sort_small(x, values, forward, comparator);
+        config.record_swaps(swaps);
+        config.record_call(level, false);
+    } else {
         let mid_point = x.len() / 2;
         // xを事前に分割しておく(これもだめ)
         // let first = &mut x[..mid_point];
         // let second = &mut x[mid_point..];
 
         // xをmid_pointを境にした2つの可変の借用に分割し、firstとsecondに束縛する
+        // valuesも同じ位置で分割する
         let (first, second) = x.split_at_mut(mid_point);
+        let (first_values, second_values) = values.split_at_mut(mid_point);
 
+        // 前半をforwardとは逆順に、後半をforwardの順にソートする
+        // こうしておくと、要素数が2のべき乗でなくてもsub_sortでマージできる
         // xの分割後の要素数をしきい値と比較する
-        if mid_point >= PARALLEL_THRESHOLD {
+        let joined = config.should_split(mid_point, depth);
+        if joined {
             // しきい値以上なら並列にソートする(並列処理)
-            rayon::join(|| do_sort(first, true, comparator),
-                        || do_sort(second, false, comparator));
+            rayon::join(|| do_sort(first, first_values, !forward, comparator, config, depth + 1, level + 1),
+                        || do_sort(second, second_values, forward, comparator, config, depth + 1, level + 1));
         } else {
             // しきい値未満なら順番にソートする(順次処理)
-            do_sort(&mut x[..mid_point], true, comparator);
-            do_sort(&mut x[mid_point..], false, comparator);
+            do_sort(first, first_values, !forward, comparator, config, depth, level + 1);
+            do_sort(second, second_values, forward, comparator, config, depth, level + 1);
         }
-        sub_sort(x, forward, comparator);
+        config.record_call(level, joined);
+        // 通知の単位の部分列では、前半と後半のソートを終えたことを通知する
+        // 続くsub_sortの分は、sub_sortが通知する
+        if x.len() == PROGRESS_CHUNK {
+            config.record_progress(2 * do_sort_work(mid_point));
+        }
+        sub_sort(x, values, forward, comparator, config, depth, level + 1);
     }
 }
 
-fn sub_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+fn sub_sort<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F,
+                     config: &SortConfig, depth: usize, level: usize)
     where T: Send,
+          V: Send,
           F: Sync + Fn(&T, &T) -> Ordering
 {
+    if config.skip_if_cancelled() {
+        return;
+    }
     if x.len() > 1 {
-        compare_and_swap(x, forward, comparator);
-        let mid_point = x.len() / 2;
+        let swaps = compare_and_swap(x, values, forward, comparator);
+        config.record_swaps(swaps);
+        if x.len() > PROGRESS_CHUNK {
+            config.record_progress(x.len());
+        }
+        // 要素数が2のべき乗ならx.len() / 2と同じ位置で分割する
+        let mid_point = greatest_power_of_two_less_than(x.len());
         let (first, second) = x.split_at_mut(mid_point);
-        if mid_point >= PARALLEL_THRESHOLD {
-            rayon::join(|| sub_sort(first, forward, comparator),
-                        || sub_sort(second, forward, comparator));
+        let (first_values, second_values) = values.split_at_mut(mid_point);
+        let joined = config.should_split(mid_point, depth);
+        if joined {
+            rayon::join(|| sub_sort(first, first_values, forward, comparator, config, depth + 1, level + 1),
+                        || sub_sort(second, second_values, forward, comparator, config, depth + 1, level + 1));
+        } else {
+            sub_sort(first, first_values, forward, comparator, config, depth, level + 1);
+            sub_sort(second, second_values, forward, comparator, config, depth, level + 1);
         }
+        config.record_call(level, joined);
+    } else {
+        config.record_call(level, false);
+    }
+    if x.len() == PROGRESS_CHUNK {
+        config.record_progress(sub_sort_work(x.len()));
     }
 }
 
-fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+// 交換した回数を返す
+fn compare_and_swap<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F) -> usize
     where F: Fn(&T, &T) -> Ordering
 {
     // 比較に先立ちforward(bool)をOrdering値に変換しておく
@@ -70,75 +315,429 @@ fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
     } else {
         Ordering::Less
     };
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+    // 要素数が2のべき乗でないときは、末尾に仮想的な要素があるものとして
+    // 相手が存在する要素だけを比較する
+    let mid_point = greatest_power_of_two_less_than(x.len());
+    let mut swaps = 0;
+    for i in 0..(x.len() - mid_point) {
         // comparatorクロージャで2要素を比較し、返されたOrderingのバリアントが
         // swap_conditionと等しいなら要素を交換する
+        // キーと値を同時に交換するので、2つの列の対応が崩れない
         if comparator(&x[i], &x[mid_point + i]) == swap_condition {
             x.swap(i, mid_point + i);
+            values.swap(i, mid_point + i);
+            swaps += 1;
         }
     }
+    swaps
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    sort_by_with_config(x, comparator, &SortConfig::default())
+}
+
+// 要素数が2のべき乗でなくてもソートできるsort_by
+pub fn sort_by_arbitrary<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    do_sort(x, &mut no_values(x.len()), true, comparator, &SortConfig::default(), 0, 0);
+    check_sorted_by(x, comparator);
+    Ok(())
+}
+
+// 並列処理の設定を指定してソートする
+pub fn sort_with_config<T: Ord + Send>(x: &mut [T], order: &SortOrder, config: &SortConfig)
+    -> Result<(), SortError>
+{
+    match *order {
+        SortOrder::Ascending => sort_by_with_config(x, &|a, b| a.cmp(b), config),
+        SortOrder::Descending => sort_by_with_config(x, &|a, b| b.cmp(a), config),
+    }
 }
 
-pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+// 並列処理の設定を指定してsort_byする
+pub fn sort_by_with_config<T, F>(x: &mut [T], comparator: &F, config: &SortConfig)
+    -> Result<(), SortError>
     where T: Send,
           F: Sync + Fn(&T, &T) -> Ordering
 {
     if x.len().is_power_of_two() {
-        do_sort(x, true, comparator);
+        let mut values = no_values(x.len());
+        config.run(|| do_sort(x, &mut values, true, comparator, config, 0, 0));
+        // check-sortedフィーチャーを有効にしたときは、ソート結果がcomparatorの順に並んでいることを検証する
+        check_sorted_by(x, comparator);
         Ok(())
     } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
+        Err(SortError::NotPowerOfTwo(x.len()))
+    }
+}
+
+// ソートしながら比較や交換の回数を数え、統計を返す
+// 理論上の比較回数や、しきい値によってrayon::joinで分割した回数を確かめるのに使う
+// カウンタを複数のスレッドで共有するので、sort_with_configより遅くなる
+pub fn sort_with_stats<T: Ord + Send>(x: &mut [T], order: &SortOrder, config: &SortConfig)
+    -> Result<SortStats, SortError>
+{
+    match *order {
+        SortOrder::Ascending => sort_by_with_stats(x, &|a, b| a.cmp(b), config),
+        SortOrder::Descending => sort_by_with_stats(x, &|a, b| b.cmp(a), config),
     }
 }
 
+pub fn sort_by_with_stats<T, F>(x: &mut [T], comparator: &F, config: &SortConfig)
+    -> Result<SortStats, SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    if !x.len().is_power_of_two() {
+        return Err(SortError::NotPowerOfTwo(x.len()));
+    }
+    let counters = StatsCounters::default();
+    // 比較の回数は、comparatorを包んだクロージャで数える
+    let counting = |a: &T, b: &T| {
+        counters.comparisons.fetch_add(1, Relaxed);
+        comparator(a, b)
+    };
+    let config = SortConfig { stats: Some(&counters), ..*config };
 
-// このモジュールはcargo testを実行したときのみコンパイルされる
-#[cfg(test)]
-mod tests {
-    use super::{sort, sort_by};
-    use crate::SortOrder::*;
-    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+    let mut values = no_values(x.len());
+    config.run(|| do_sort(x, &mut values, true, &counting, &config, 0, 0));
+    check_sorted_by(x, comparator);
+    Ok(counters.to_stats())
+}
+
+// ソートの進捗をprogressに通知しながらソートする
+// cancelにtrueが設定されると、do_sortとsub_sortの区切りでソートを中断してCancelledを返す
+// 残りの処理がなくなってから設定されたときは、ソートを終えてOkを返す
+// 中断したときのxの並びは不定だが、元の要素を並べ替えたものになっている
+//
+// progressは処理を終えた仕事量と全体の仕事量を引数に呼ばれ、最後は両者が等しくなる
+// 複数のスレッドから呼ばれるので、呼ばれる順序と仕事量の順序は一致しないことがある
+//
+//   let cancel = AtomicBool::new(false);
+//   fourth::sort_with_progress(&mut x, &SortOrder::Ascending, &SortConfig::new(), &cancel,
+//                              &|done, total| eprintln!("{}%", done * 100 / total))?;
+pub fn sort_with_progress<T, P>(x: &mut [T], order: &SortOrder, config: &SortConfig,
+                                cancel: &AtomicBool, progress: &P) -> Result<(), SortError>
+    where T: Ord + Send,
+          P: Sync + Fn(usize, usize)
+{
+    match *order {
+        SortOrder::Ascending => sort_by_with_progress(x, &|a, b| a.cmp(b), config, cancel, progress),
+        SortOrder::Descending => sort_by_with_progress(x, &|a, b| b.cmp(a), config, cancel, progress),
+    }
+}
+
+pub fn sort_by_with_progress<T, F, P>(x: &mut [T], comparator: &F, config: &SortConfig,
+                                      cancel: &AtomicBool, progress: &P) -> Result<(), SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering,
+          P: Sync + Fn(usize, usize)
+{
+    if !x.len().is_power_of_two() {
+        return Err(SortError::NotPowerOfTwo(x.len()));
+    }
+    let total = do_sort_work(x.len());
+    let state = Progress { done: AtomicUsize::new(0), total, callback: progress };
+    let cancel = Cancel { requested: cancel, skipped: AtomicBool::new(false) };
+    let config = SortConfig { cancel: Some(&cancel), progress: Some(&state), ..*config };
+
+    let mut values = no_values(x.len());
+    config.run(|| do_sort(x, &mut values, true, comparator, &config, 0, 0));
+    // 最後の通知の後に中断が要求されても、ソートは終わっているのでOkを返す
+    if cancel.skipped.load(Relaxed) {
+        return Err(SortError::Cancelled);
+    }
+    // 通知の単位より小さな列では、ソートを終えたときにまとめて通知する
+    if x.len() < PROGRESS_CHUNK {
+        config.record_progress(total);
+    }
+    check_sorted_by(x, comparator);
+    Ok(())
+}
+
+// 要素から取り出したキーでソートする(slice::sort_by_keyに相当)
+// キーは比較のたびに計算される
+pub fn sort_by_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+    where T: Send,
+          K: Ord,
+          F: Sync + Fn(&T) -> K
+{
+    sort_by(x, &|a, b| key(a).cmp(&key(b)))
+}
+
+// 要素から取り出したキーでソートする(slice::sort_by_cached_keyに相当)
+// キーは要素ごとに一度だけ、並列に計算される
+// sort_by_arbitraryと同じく、要素数は2のべき乗でなくてよい
+pub fn sort_by_cached_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+    where T: Send + Sync,
+          K: Ord + Send,
+          F: Sync + Fn(&T) -> K
+{
+    // キーと元のインデックスの組をソートする
+    // インデックスも比較に含めるので、キーが等しい要素があっても順序が一意に決まる
+    let mut keys: Vec<_> = x.par_iter().map(key).enumerate().map(|(i, k)| (k, i)).collect();
+    sort_by_arbitrary(&mut keys, &|a, b| a.cmp(b))?;
+
+    let mut perm: Vec<_> = keys.into_iter().map(|(_, i)| i).collect();
+    apply_permutation(x, &mut perm);
+    Ok(())
+}
+
+// 安定ソート。comparatorで等しい要素は、ソート前の順序を保つ(slice::sort_byと同じ結果になる)
+// 要素そのものではなくインデックスの列を並列にソートし、最後に要素を並べ替える
+// インデックスの列はsort_by_arbitraryでソートするので、要素数は2のべき乗でなくてよい
+pub fn sort_by_stable<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where T: Sync,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    // 等しい要素は元のインデックスで比較するので、順序が入れ替わらない
+    let mut perm: Vec<usize> = (0..x.len()).collect();
+    sort_by_arbitrary(&mut perm, &|&i, &j| comparator(&x[i], &x[j]).then(i.cmp(&j)))?;
+
+    apply_permutation(x, &mut perm);
+    Ok(())
+}
+
+// キーの列keysをソートし、値の列valuesの要素もkeysと同じ位置に並べ替える
+// (キー, 値)のタプルのベクタを作らずに、キーと値の組をソートできる
+// sort_byと同じく、しきい値以上の要素数では並列にソートする
+pub fn sort_pairs<K: Ord + Send, V: Send>(keys: &mut [K], values: &mut [V], order: &SortOrder)
+    -> Result<(), SortError>
+{
+    match *order {
+        SortOrder::Ascending => sort_pairs_by(keys, values, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_pairs_by(keys, values, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_pairs_by<K, V, F>(keys: &mut [K], values: &mut [V], comparator: &F)
+    -> Result<(), SortError>
+    where K: Send,
+          V: Send,
+          F: Sync + Fn(&K, &K) -> Ordering
+{
+    if keys.len() != values.len() {
+        return Err(SortError::LengthMismatch(keys.len(), values.len()));
+    }
+    if !keys.len().is_power_of_two() {
+        return Err(SortError::NotPowerOfTwo(keys.len()));
+    }
+    do_sort(keys, values, true, comparator, &SortConfig::default(), 0, 0);
+    check_sorted_by(keys, comparator);
+    Ok(())
+}
+
+// keysをソートし、ソート後の各要素の元のインデックスを返す
+// 返す列permのperm[i]は、ソート後にi番目に来た要素のソート前の位置になる
+// キーが等しい要素の順序は不定。ソート前の順序を保つにはsort_by_stableを使う
+pub fn argsort<K: Ord + Send>(keys: &mut [K], order: &SortOrder) -> Result<Vec<usize>, SortError> {
+    match *order {
+        SortOrder::Ascending => argsort_by(keys, &|a, b| a.cmp(b)),
+        SortOrder::Descending => argsort_by(keys, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn argsort_by<K, F>(keys: &mut [K], comparator: &F) -> Result<Vec<usize>, SortError>
+    where K: Send,
+          F: Sync + Fn(&K, &K) -> Ordering
+{
+    // インデックスの列を値としてキーと一緒に並べ替える
+    let mut perm: Vec<usize> = (0..keys.len()).collect();
+    sort_pairs_by(keys, &mut perm, comparator)?;
+    Ok(perm)
+}
 
-    // 構造体Studentを定義する
-    // deriveアトリビュートを使い、DebugトレイととPartialEqトレイとの実装を自動導出する
-    #[derive(Debug, PartialEq)]
-    struct Student {
-        first_name: String,
-        last_name: String,
-        age: u8,
+// 先頭のk要素だけをソートする
+// 実行後、x[..k]にはorderの順で先頭からk個の要素が並ぶ。x[k..]の並びは不定
+pub fn partial_sort<T: Ord + Send>(x: &mut [T], k: usize, order: &SortOrder)
+    -> Result<(), SortError>
+{
+    match *order {
+        SortOrder::Ascending => partial_sort_by(x, k, &|a, b| a.cmp(b)),
+        SortOrder::Descending => partial_sort_by(x, k, &|a, b| b.cmp(a)),
     }
+}
+
+// comparatorの順で先頭のk要素だけをソートする。kがx.len()より大きいときは全体をソートする
+pub fn partial_sort_by<T, F>(x: &mut [T], k: usize, comparator: &F) -> Result<(), SortError>
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    let k = k.min(x.len());
+    if k > 0 {
+        // k以上で最小の2のべき乗を単位にして、上位の要素を集めていく
+        let block = k.next_power_of_two();
+        let config = SortConfig::default();
+        do_partial_sort(x, block, comparator, &config, 0);
+        check_sorted_by(&x[..k], comparator);
+    }
+    Ok(())
+}
 
-    // implブロックを使うと対象の型に関連関数やメソッドを実装できる
-    impl Student {
+// orderの順で先頭からk個の要素を、xを変更せずにベクタで返す
+// 例えばDescendingを指定すると、大きい方からk個の要素が得られる
+pub fn top_k<T: Ord + Send + Sync + Clone>(x: &[T], k: usize, order: &SortOrder)
+    -> Result<Vec<T>, SortError>
+{
+    match *order {
+        SortOrder::Ascending => top_k_by(x, k, &|a, b| a.cmp(b)),
+        SortOrder::Descending => top_k_by(x, k, &|a, b| b.cmp(a)),
+    }
+}
 
-        // 関連関数newを定義する
-        fn new(first_name: &str, last_name: &str, age: u8) -> Self {
+// comparatorの順で先頭からk個の要素を、xを変更せずにベクタで返す
+pub fn top_k_by<T, F>(x: &[T], k: usize, comparator: &F) -> Result<Vec<T>, SortError>
+    where T: Send + Sync + Clone,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    let k = k.min(x.len());
+    if k == 0 {
+        return Ok(Vec::new());
+    }
+    let block = k.next_power_of_two();
+    let config = SortConfig::default();
 
-            Self {
-                // 構造体Studentを初期化して返す。Selfはimpl対象の型(Student)の別名
-                first_name: first_name.to_string(),
-                last_name: last_name.to_string(),
-                age, // フィールドと変数が同じ名前のときは、このように省略することができる
+    // xをまるごと複製せず、block要素ずつ並列に複製してそれぞれの上位block個を求め、
+    // 隣り合う結果をマージしながら上位block個に絞り込んでいく
+    let mut top = x.par_chunks(block)
+        .map(|chunk| {
+            let mut top = chunk.to_vec();
+            do_partial_sort(&mut top, block, comparator, &config, 0);
+            top
+        })
+        .reduce(Vec::new, |mut top, other| {
+            // reduceはチャンクの順序を保つので、block個未満の結果は最後のチャンクを含むものだけ
+            // それより後ろに結果はないので、topがblock個未満ならotherは空になる
+            if top.is_empty() {
+                return other;
             }
-        }
+            if !other.is_empty() {
+                top.extend(other);
+                merge_top(&mut top, block, comparator, &config, 0);
+                top.truncate(block);
+            }
+            top
+        });
+    top.truncate(k);
+    check_sorted_by(&top, comparator);
+    Ok(top)
+}
+
+// x[..block]に、comparatorの順で先頭からblock個の要素を昇順に並べる
+// blockは2のべき乗であること。xの要素数に制約はない
+fn do_partial_sort<T, F>(x: &mut [T], block: usize, comparator: &F, config: &SortConfig, depth: usize)
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    if x.len() <= block {
+        do_sort(x, &mut no_values(x.len()), true, comparator, config, depth, 0);
+        return;
+    }
+
+    // 前半と後半それぞれの先頭に、それぞれの上位block個(後半はblock個未満のこともある)を集める
+    // 前半の要素数をblockの倍数にしておくと、前半は常にblock個以上になり、
+    // 後半の上位の要素を前半の上位block個の直後に移せる
+    let blocks = (x.len() - 1) / block + 1;
+    let mid_point = (blocks - blocks / 2) * block;
+    let (first, second) = x.split_at_mut(mid_point);
+    if config.should_split(mid_point, depth) {
+        rayon::join(|| do_partial_sort(first, block, comparator, config, depth + 1),
+                    || do_partial_sort(second, block, comparator, config, depth + 1));
+    } else {
+        do_partial_sort(first, block, comparator, config, depth);
+        do_partial_sort(second, block, comparator, config, depth);
+    }
+
+    // 後半の上位top個を前半の上位block個の直後に移し、2つをマージする
+    let top = (x.len() - mid_point).min(block);
+    if mid_point > block {
+        let (first, second) = x.split_at_mut(mid_point);
+        first[block..block + top].swap_with_slice(&mut second[..top]);
+    }
+    merge_top(&mut x[..block + top], block, comparator, config, depth);
+}
+
+// x[..block]とx[block..]がそれぞれ昇順のとき、
+// x[..block]に、2つをマージした列の先頭block個を昇順に並べる
+// x[block..]の要素数はblock以下であること
+fn merge_top<T, F>(x: &mut [T], block: usize, comparator: &F, config: &SortConfig, depth: usize)
+    where T: Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    debug_assert!(block <= x.len() && x.len() <= 2 * block);
+    if x.len() == block {
+        return;
+    }
+    // 前半を逆順にすると、前半が逆順、後半が正順の列になる
+    x[..block].reverse();
+    // 後半の要素数をblockにそろえるため、後半の末尾に最大の要素を補ったものとみなす
+    // compare_and_swapはx[i]とx[block + i]を比べるので、補った要素との比較は省ける
+    // 前半に小さい方を集めると、x[..block]が全体の上位block個のバイトニック列になる
+    // 後半は先頭block要素に影響しないので、前半だけをsub_sortでマージする
+    compare_and_swap(x, &mut no_values(x.len()), true, comparator);
+    sub_sort(&mut x[..block], &mut no_values(block), true, comparator, config, depth, 0);
+}
+
+// orderの順にソート済みの2つの列aとbを、1つのソート済みの列にまとめて返す
+// バイトニックマージを使うので、aとbの要素数に制約はない
+// sub_sortと同じく、しきい値以上の要素数では並列にマージする
+pub fn merge<T: Ord + Clone + Send>(a: &[T], b: &[T], order: &SortOrder) -> Vec<T> {
+    match *order {
+        SortOrder::Ascending => merge_by(a, b, &|x, y| x.cmp(y)),
+        SortOrder::Descending => merge_by(a, b, &|x, y| y.cmp(x)),
     }
+}
+
+// comparatorの順にソート済みの2つの列aとbを、1つのソート済みの列にまとめて返す
+pub fn merge_by<T, F>(a: &[T], b: &[T], comparator: &F) -> Vec<T>
+    where T: Clone + Send,
+          F: Sync + Fn(&T, &T) -> Ordering
+{
+    check_sorted_by(a, comparator);
+    check_sorted_by(b, comparator);
+    // aを逆順にしてbの前につなげると、前半が逆順、後半が正順の列になる
+    // do_sortがsub_sortに渡すのと同じ形なので、そのままsub_sortでマージできる
+    let mut x: Vec<T> = a.iter().rev().chain(b).cloned().collect();
+    sub_sort(&mut x, &mut no_values(a.len() + b.len()), true, comparator, &SortConfig::default(), 0, 0);
+    x
+}
+
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{sort, sort_by, sort_arbitrary, sort_by_key, sort_by_cached_key, sort_by_stable};
+    use super::merge;
+    use super::{sort_pairs, argsort};
+    use super::{partial_sort, partial_sort_by, top_k, top_k_by};
+    use super::{SortConfig, sort_with_config, sort_by_with_config};
+    use super::{SortStats, sort_with_stats, sort_by_with_stats};
+    use super::{sort_with_progress, sort_by_with_progress};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+    use crate::utils::{new_vec, is_sorted_by, is_sorted_by_key, Pattern};
+    use crate::tests::{Student, students};
 
     #[test]
     // 年齢で昇順にソートする
     fn sort_students_by_age_ascending() {
 
         // テストデータを作成
-        let taro = Student::new("Taro", "Yamada", 16);
-        let hanako = Student::new("Hanako", "Yamada", 14);
-        let kyoko = Student::new("Kyoko", "Ito", 15);
-        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+        let students = students();
+        let (taro, hanako, kyoko, ryosuke) = (&students[0], &students[1], &students[2], &students[3]);
 
         // ソート対象のベクタを作成する
-        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        let mut x = vec![taro, hanako, kyoko, ryosuke];
 
         // ソート後の期待値を作成する
-        let expected = vec![&hanako, &kyoko, &taro, &ryosuke];
+        let expected = vec![hanako, kyoko, taro, ryosuke];
 
         assert_eq!(
             // sort_by関数でソートする。第二引数はソート順を決めるクロージャ
@@ -155,14 +754,12 @@ mod tests {
     // 名前で昇順にソートする
     fn sort_students_by_name_ascending() {
 
-        let taro = Student::new("Taro", "Yamada", 16);
-        let hanako = Student::new("Hanako", "Yamada", 14);
-        let kyoko = Student::new("Kyoko", "Ito", 15);
-        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+        let students = students();
+        let (taro, hanako, kyoko, ryosuke) = (&students[0], &students[1], &students[2], &students[3]);
 
-        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        let mut x = vec![taro, hanako, kyoko, ryosuke];
 
-        let expected = vec![&ryosuke, &kyoko, &hanako, &taro];
+        let expected = vec![ryosuke, kyoko, hanako, taro];
 
         assert_eq!(
             sort_by(&mut x,
@@ -197,4 +794,345 @@ mod tests {
             
         }
     }
+
+    #[test]
+    fn sort_u32_arbitrary_length() {
+        // 並列処理のしきい値をまたぐ、2のべき乗でない要素数のデータ列もソートできる
+        for &n in &[0, 1, 3, 5, 100, 4095, 8193, 100_000] {
+            let mut x = new_u32_vec(n);
+            assert_eq!(sort_arbitrary(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x));
+
+            let mut x = new_u32_vec(n);
+            assert_eq!(sort_arbitrary(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x));
+        }
+    }
+
+    #[test]
+    fn sort_u32_by_cached_key_large() {
+        let mut x = new_u32_vec(65536);
+        // 降順になるようなキーを与える
+        assert_eq!(sort_by_cached_key(&mut x, &|&v| std::cmp::Reverse(v)), Ok(()));
+        assert!(is_sorted_descending(&x));
+
+        // 要素数が2のべき乗でなくてもよい
+        let mut x = new_u32_vec(1000);
+        assert_eq!(sort_by_cached_key(&mut x, &|&v| v), Ok(()));
+        assert!(is_sorted_ascending(&x));
+    }
+
+    #[test]
+    fn sort_u32_with_config() {
+        // しきい値を小さくして、rayon::joinが何度も呼ばれるようにする
+        let config = SortConfig::new().threshold(64);
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort_with_config(&mut x, &Ascending, &config), Ok(()));
+        assert!(is_sorted_ascending(&x));
+
+        // 入れ子の深さを0にすると順次処理になる
+        let config = SortConfig::new().threshold(64).max_depth(0);
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort_with_config(&mut x, &Descending, &config), Ok(()));
+        assert!(is_sorted_descending(&x));
+
+        let mut x = new_u32_vec(3);
+        assert_eq!(sort_with_config(&mut x, &Ascending, &config),
+                   Err(crate::SortError::NotPowerOfTwo(3)));
+    }
+
+    #[test]
+    fn sort_u32_on_thread_pool() {
+        // 2スレッドのプールを作り、ソートがそのプール上で実行されることを確認する
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        let config = SortConfig::new().threshold(64).max_depth(4).thread_pool(&pool);
+
+        let max_threads = AtomicUsize::new(0);
+        let mut x = new_u32_vec(65536);
+        assert_eq!(
+            sort_by_with_config(&mut x, &|a, b| {
+                max_threads.fetch_max(rayon::current_num_threads(), Ordering::Relaxed);
+                a.cmp(b)
+            }, &config),
+            Ok(())
+        );
+        assert!(is_sorted_ascending(&x));
+        assert_eq!(max_threads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    // 期待値を手で書く代わりに、is_sorted_byで結果を検証する
+    fn sort_many_students_checked_by_comparator() {
+        let last_names: Vec<String> = new_vec(8192, Pattern::FewUnique { unique: 50 }, 1);
+        let first_names: Vec<String> = new_vec(8192, Pattern::Random, 2);
+        let ages: Vec<u32> = new_vec(8192, Pattern::Random, 3);
+        let students: Vec<Student> = last_names.iter().zip(&first_names).zip(&ages)
+            .map(|((last, first), &age)| Student::new(first, last, (age % 10) as u8 + 10))
+            .collect();
+
+        let by_name = |a: &&Student, b: &&Student| a.last_name.cmp(&b.last_name)
+            .then_with(|| a.first_name.cmp(&b.first_name));
+        let mut x: Vec<&Student> = students.iter().collect();
+        assert_eq!(sort_by(&mut x, &by_name), Ok(()));
+        assert!(is_sorted_by(&x, &by_name));
+
+        let mut x: Vec<&Student> = students.iter().collect();
+        assert_eq!(sort_by_key(&mut x, &|s| s.age), Ok(()));
+        assert!(is_sorted_by_key(&x, &|s| s.age));
+        assert!(!is_sorted_by(&x, &by_name));
+    }
+
+    #[test]
+    fn sort_u32_stable_matches_std() {
+        // 値の種類が少ない列を、上位の桁だけで比べて安定ソートする
+        let x = new_u32_vec(65536);
+        let mut expected = x.clone();
+        expected.sort_by_key(|v| v >> 28);
+
+        let mut y = x.clone();
+        assert_eq!(sort_by_stable(&mut y, &|a, b| (a >> 28).cmp(&(b >> 28))), Ok(()));
+        assert_eq!(y, expected);
+
+        // 要素数が2のべき乗でなくてもよい
+        let x = new_u32_vec(1000);
+        let mut expected = x.clone();
+        expected.sort_by_key(|v| v >> 30);
+        let mut y = x.clone();
+        assert_eq!(sort_by_stable(&mut y, &|a, b| (a >> 30).cmp(&(b >> 30))), Ok(()));
+        assert_eq!(y, expected);
+    }
+
+    #[test]
+    fn partial_sort_matches_full_sort() {
+        let x = new_u32_vec(65536);
+        let mut sorted = x.clone();
+        sorted.sort();
+
+        for &k in &[0, 1, 7, 100, 4096, 5000, 65536, 100_000] {
+            let k_clamped = k.min(x.len());
+
+            let mut y = x.clone();
+            assert_eq!(partial_sort(&mut y, k, &Ascending), Ok(()));
+            assert_eq!(&y[..k_clamped], &sorted[..k_clamped]);
+
+            // 残りの要素も含めて、ソート前の要素の並べ替えになっている
+            y.sort();
+            assert_eq!(y, sorted);
+        }
+
+        // 要素数が2のべき乗でなくてもよい
+        for &len in &[3, 17, 1000, 50_001] {
+            let x = new_u32_vec(len);
+            let mut sorted = x.clone();
+            sorted.sort();
+            for &k in &[1, 3, 16, 100, 4097] {
+                let k_clamped = k.min(len);
+                let mut y = x.clone();
+                assert_eq!(partial_sort(&mut y, k, &Ascending), Ok(()));
+                assert_eq!(&y[..k_clamped], &sorted[..k_clamped]);
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_largest() {
+        let x = new_u32_vec(16384);
+        let mut expected = x.clone();
+        expected.sort_by(|a, b| b.cmp(a));
+        expected.truncate(10);
+        assert_eq!(top_k(&x, 10, &Descending), Ok(expected));
+
+        // 要素数が2のべき乗でなくてもよい
+        let x = new_u32_vec(10_007);
+        let mut expected = x.clone();
+        expected.sort();
+        for &k in &[1, 3, 100, 5000, 20_000] {
+            assert_eq!(top_k(&x, k, &Ascending), Ok(expected[..k.min(x.len())].to_vec()));
+        }
+
+        // f64は比較関数を渡す
+        let x: Vec<f64> = new_vec(16384, Pattern::Random, 1);
+        let mut expected = x.clone();
+        expected.sort_by(|a, b| a.total_cmp(b));
+        expected.truncate(5);
+        assert_eq!(top_k_by(&x, 5, &|a: &f64, b| a.total_cmp(b)), Ok(expected));
+
+        // 名前と年齢の組を、年齢の若い順に2人選ぶ
+        let mut x = vec![("Taro", 16), ("Hanako", 14), ("Kyoko", 15), ("Ryosuke", 17)];
+        assert_eq!(partial_sort_by(&mut x, 2, &|a, b| a.1.cmp(&b.1)), Ok(()));
+        assert_eq!(&x[..2], &[("Hanako", 14), ("Kyoko", 15)]);
+    }
+
+    #[test]
+    fn merge_sorted_runs() {
+        // 長さの異なるソート済みの列をマージする
+        for &(n, m) in &[(0, 0), (0, 5), (1, 0), (3, 5), (100, 37), (65536, 12345)] {
+            let mut a = new_u32_vec(n);
+            let mut b: Vec<u32> = new_u32_vec(n + m)[n..].to_vec();
+            let mut expected: Vec<u32> = a.iter().chain(&b).cloned().collect();
+
+            a.sort();
+            b.sort();
+            expected.sort();
+            assert_eq!(merge(&a, &b, &Ascending), expected);
+
+            a.reverse();
+            b.reverse();
+            expected.reverse();
+            assert_eq!(merge(&a, &b, &Descending), expected);
+        }
+    }
+
+    #[test]
+    fn argsort_u32_large() {
+        // しきい値を超える要素数で、並列にソートしても対応が崩れないことを確かめる
+        let original = new_u32_vec(65536);
+        let mut keys = original.clone();
+        let perm = argsort(&mut keys, &Ascending).unwrap();
+        assert!(is_sorted_ascending(&keys));
+        assert!(perm.iter().zip(&keys).all(|(&i, &k)| original[i] == k));
+
+        let mut seen = vec![false; perm.len()];
+        perm.iter().for_each(|&i| seen[i] = true);
+        assert!(seen.into_iter().all(|b| b));
+    }
+
+    #[test]
+    fn sort_pairs_length_mismatch() {
+        let mut keys = vec![1u32, 2];
+        let mut values = vec![1u32];
+        assert_eq!(sort_pairs(&mut keys, &mut values, &Descending),
+                   Err(crate::SortError::LengthMismatch(2, 1)));
+    }
+
+    #[test]
+    fn stats_count_network_comparisons() {
+        // 並列処理をしなければ、比較の回数はネットワークの比較器の数と一致する
+        // 1024要素なら、16要素の固定長のネットワーク64個と、バイトニックマージの比較器の合計
+        let mut x = new_u32_vec(1024);
+        let config = SortConfig::new().max_depth(0);
+        let stats = sort_with_stats(&mut x, &Ascending, &config).unwrap();
+        assert!(is_sorted_ascending(&x));
+        assert_eq!(stats.comparisons, 28_160 - 64 * 20);
+        assert!(stats.swaps > 0 && stats.swaps <= stats.comparisons);
+        assert_eq!(stats.joins, 0);
+        // do_sortを1024から32要素まで6段、最後のsub_sortを1024から1要素まで11段たどる
+        assert_eq!(stats.max_recursion_depth, 11);
+
+        // 昇順に並んだ要素を昇順にソートしても、前半を降順に並べるので交換は0回にならない
+        let stats = sort_with_stats(&mut x, &Ascending, &config).unwrap();
+        assert!(stats.swaps > 0);
+    }
+
+    #[test]
+    fn stats_count_joins_above_threshold() {
+        // しきい値未満の要素数では分割しない
+        let mut x = new_u32_vec(1024);
+        let stats = sort_with_stats(&mut x, &Descending, &SortConfig::new()).unwrap();
+        assert!(is_sorted_descending(&x));
+        assert_eq!(stats.joins, 0);
+
+        // しきい値を下げると、分割した回数が増える
+        let mut counts = Vec::new();
+        for &threshold in &[512, 128, 32] {
+            let mut x = new_u32_vec(1024);
+            let config = SortConfig::new().threshold(threshold);
+            let stats = sort_with_stats(&mut x, &Descending, &config).unwrap();
+            assert!(is_sorted_descending(&x));
+            assert_eq!(stats.comparisons, 28_160 - 64 * 20);
+            counts.push(stats.joins);
+        }
+        assert!(counts[0] > 0 && counts[0] < counts[1] && counts[1] < counts[2]);
+    }
+
+    #[test]
+    fn stats_by_comparator() {
+        let mut x = vec![10u32, 30, 11, 20];
+        let stats = sort_by_with_stats(&mut x, &|a, b| b.cmp(a), &SortConfig::new());
+        assert_eq!(x, vec![30, 20, 11, 10]);
+        // 4要素は固定長のネットワークだけでソートする
+        assert_eq!(stats.map(|s| s.comparisons), Ok(5));
+        assert_eq!(stats.map(|s| s.max_recursion_depth), Ok(0));
+
+        let mut x = vec![10u32, 30, 11];
+        assert_eq!(sort_by_with_stats(&mut x, &|a, b| a.cmp(b), &SortConfig::new()),
+                   Err(crate::SortError::NotPowerOfTwo(3)));
+        assert_eq!(SortStats::default().comparisons, 0);
+    }
+
+    #[test]
+    fn progress_reaches_total() {
+        let mut x = new_u32_vec(1 << 17);
+        let cancel = AtomicBool::new(false);
+        let reports = Mutex::new(Vec::new());
+        let progress = |done, total| reports.lock().unwrap().push((done, total));
+        assert_eq!(sort_with_progress(&mut x, &Ascending, &SortConfig::new(), &cancel, &progress), Ok(()));
+        assert!(is_sorted_ascending(&x));
+
+        // 通知の単位ごとに何度も通知され、最後は全体の仕事量に達する
+        let reports = reports.into_inner().unwrap();
+        assert!(reports.len() > 1);
+        let total = reports[0].1;
+        assert!(reports.iter().all(|&(done, t)| t == total && done <= total));
+        assert_eq!(reports.iter().map(|&(done, _)| done).max(), Some(total));
+
+        // 小さな列では、終わったときに一度だけ通知される
+        let mut x = vec![10u32, 30, 11, 20];
+        let count = AtomicUsize::new(0);
+        let progress = |done, total| {
+            assert_eq!(done, total);
+            count.fetch_add(1, Ordering::Relaxed);
+        };
+        assert_eq!(sort_by_with_progress(&mut x, &|a, b| b.cmp(a), &SortConfig::new(), &cancel, &progress),
+                   Ok(()));
+        assert_eq!(x, vec![30, 20, 11, 10]);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn cancel_leaves_permutation() {
+        let original = new_u32_vec(1 << 17);
+        let mut x = original.clone();
+        let cancel = AtomicBool::new(false);
+        // 最初の通知で中断を要求する
+        let progress = |_, _| cancel.store(true, Ordering::Relaxed);
+        assert_eq!(sort_with_progress(&mut x, &Ascending, &SortConfig::new(), &cancel, &progress),
+                   Err(crate::SortError::Cancelled));
+        assert!(!is_sorted_ascending(&x));
+
+        // 途中で中断しても、要素が失われたり重複したりしない
+        let mut sorted = x.clone();
+        let mut expected = original.clone();
+        sorted.sort();
+        expected.sort();
+        assert_eq!(sorted, expected);
+
+        // 始める前に中断が要求されていれば、何もせずに返る
+        let mut x = original.clone();
+        assert_eq!(sort_with_progress(&mut x, &Descending, &SortConfig::new(), &cancel, &|_, _| {}),
+                   Err(crate::SortError::Cancelled));
+        assert_eq!(x, original);
+
+        let mut x = vec![10u32, 30, 11];
+        assert_eq!(sort_with_progress(&mut x, &Ascending, &SortConfig::new(), &cancel, &|_, _| {}),
+                   Err(crate::SortError::NotPowerOfTwo(3)));
+    }
+
+    #[test]
+    fn cancel_after_last_progress_is_not_cancelled() {
+        // 最後の通知で中断を要求しても、処理は残っていないのでソートを終えている
+        for &len in &[4, 1 << 17] {
+            let mut x = new_u32_vec(len);
+            let cancel = AtomicBool::new(false);
+            let progress = |done, total| {
+                if done == total {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            };
+            assert_eq!(sort_with_progress(&mut x, &Ascending, &SortConfig::new(), &cancel, &progress),
+                       Ok(()));
+            assert!(cancel.load(Ordering::Relaxed));
+            assert!(is_sorted_ascending(&x));
+        }
+    }
 }
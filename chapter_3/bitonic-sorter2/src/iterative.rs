@@ -0,0 +1,145 @@
+use super::{SortOrder, SortError, no_values};
+use super::networks;
+use super::utils::check_sorted_by;
+use std::cmp::Ordering;
+
+// thirdと同じソーティングネットワークを、再帰を使わずにステージとステップの二重ループで実行する
+// thirdと同じく、16要素以下のブロックを固定長のネットワークでソートしてから、
+// 前半を逆向き、後半を同じ向きに並べたブロックを順にバイトニックマージしていく
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where F: Fn(&T, &T) -> Ordering
+{
+    if x.len().is_power_of_two() {
+        do_sort(x, true, comparator);
+        // check-sortedフィーチャーを有効にしたときは、ソート結果がcomparatorの順に並んでいることを検証する
+        check_sorted_by(x, comparator);
+        Ok(())
+    } else {
+        Err(SortError::NotPowerOfTwo(x.len()))
+    }
+}
+
+pub(crate) fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+    where F: Fn(&T, &T) -> Ordering
+{
+    let len = x.len();
+    // thirdのdo_sortの再帰の末端と同じく、小さなブロックを固定長のネットワークでソートする
+    let block = len.min(networks::MAX_LEN);
+    for start in (0..len).step_by(block) {
+        let forward = block_forward(start, block, len, forward);
+        networks::sort_small(&mut x[start..start + block], &mut no_values(block), forward, comparator);
+    }
+
+    // stageはソート済みにするブロックの要素数。32, 64, 128, ...と倍にしていく
+    let mut stage = block * 2;
+    while stage <= len {
+        // stepは比較する2要素の距離。stage / 2から1まで半分にしていく
+        let mut step = stage / 2;
+        while step > 0 {
+            // 2 * step要素ごとの塊の中で、前半と後半の要素を比較する
+            for start in (0..len).step_by(2 * step) {
+                // 塊がstageのブロックのどれに属するかで向きを決める
+                // 最後のステージ(stage == len)ではすべてforwardの向きになる
+                let forward = block_forward(start, stage, len, forward);
+                compare_and_swap(&mut x[start..start + 2 * step], forward, comparator);
+            }
+            step /= 2;
+        }
+        stage *= 2;
+    }
+}
+
+// 要素数lenの列をforwardの順にソートするときに、startから始まる要素数sizeのブロックを
+// ソートする向きを返す
+// thirdのdo_sortは前半を逆向きに、後半を同じ向きにソートするので、全体からそのブロックまで
+// 分割をたどったときに、前半を選んだ回数が奇数なら逆向きになる
+// ブロックの番号start / sizeを2進数で表したとき、前半を選んだ回数はその0のビットの数になる
+fn block_forward(start: usize, size: usize, len: usize, forward: bool) -> bool {
+    let levels = (len / size).trailing_zeros();
+    let first_halves = levels - (start / size).count_ones();
+    (first_halves & 1 == 0) == forward
+}
+
+fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+    where F: Fn(&T, &T) -> Ordering
+{
+    // 比較に先立ちforward(bool)をOrdering値に変換しておく
+    let swap_condition = if forward {
+        Ordering::Greater
+    } else {
+        Ordering::Less
+    };
+    let mid_point = x.len() / 2;
+    for i in 0..mid_point {
+        if comparator(&x[i], &x[mid_point + i]) == swap_condition {
+            x.swap(i, mid_point + i);
+        }
+    }
+}
+
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{sort, sort_by};
+    use crate::{third, SortError};
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+    use crate::tests::students;
+
+    #[test]
+    // 年齢で昇順にソートする
+    fn sort_students_by_age_ascending() {
+        let students = students();
+        let (taro, hanako, kyoko, ryosuke) = (&students[0], &students[1], &students[2], &students[3]);
+
+        let mut x = vec![taro, hanako, kyoko, ryosuke];
+        let expected = vec![hanako, kyoko, taro, ryosuke];
+
+        assert_eq!(sort_by(&mut x, &|a, b| a.age.cmp(&b.age)), Ok(()));
+        assert_eq!(x, expected);
+    }
+
+    #[test]
+    fn sort_u32_large() {
+        {
+            let mut x = new_u32_vec(65536);
+            assert_eq!(sort(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x));
+        }
+        {
+            let mut x = new_u32_vec(65536);
+            assert_eq!(sort(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x));
+        }
+    }
+
+    #[test]
+    fn same_network_as_third() {
+        // 同じネットワークなら、比較の回数も、キーが等しい要素の並び順も一致する
+        for &n in &[1, 2, 16, 32, 1024] {
+            let ages: Vec<u32> = new_u32_vec(n).iter().map(|v| v % 8).collect();
+            let count = std::cell::Cell::new(0);
+            let mut x: Vec<(u32, usize)> = ages.iter().cloned().zip(0..).collect();
+            let mut y = x.clone();
+            assert_eq!(sort_by(&mut x, &|a, b| { count.set(count.get() + 1); b.0.cmp(&a.0) }), Ok(()));
+            let comparisons = count.replace(0);
+            assert_eq!(third::sort_by(&mut y, &|a, b| { count.set(count.get() + 1); b.0.cmp(&a.0) }), Ok(()));
+            assert_eq!(comparisons, count.get());
+            assert_eq!(x, y);
+        }
+    }
+
+    #[test]
+    fn sort_to_fail() {
+        let mut x = vec![10, 30, 11];
+        assert_eq!(sort(&mut x, &Ascending), Err(SortError::NotPowerOfTwo(3)));
+    }
+}
@@ -2,10 +2,461 @@ pub mod first;
 pub mod second;
 pub mod third;
 pub mod fourth;
+pub mod iterative;
+pub mod odd_even;
+pub mod networks;
+pub mod recorder;
+pub mod dispatch;
+pub mod order_by;
+pub mod batch;
+pub mod float;
+pub mod external;
+pub mod simd;
 
 pub mod utils;
 
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SortOrder {
     Ascending,
     Descending,
-}
\ No newline at end of file
+}
+
+impl SortOrder {
+    // 逆の順序を返す
+    pub fn reverse(self) -> Self {
+        match self {
+            SortOrder::Ascending => SortOrder::Descending,
+            SortOrder::Descending => SortOrder::Ascending,
+        }
+    }
+}
+
+// ソートに失敗したときのエラー
+// 呼び出し側がバリアントでマッチしたり、?演算子で伝播させたりできるように列挙型にする
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortError {
+    // 要素数が2のべき乗でなかった。問題になった要素数を持つ
+    NotPowerOfTwo(usize),
+    // 要素数が固定長のソーティングネットワークの最大の要素数を超えていた
+    TooLong(usize),
+    // キーと値の列の要素数が異なっていた。それぞれの要素数を持つ
+    LengthMismatch(usize, usize),
+    // 列の要素数が区切りの要素数の倍数でなかった。列と区切りの要素数を持つ
+    NotMultipleOfSegment(usize, usize),
+    // 要素数の異なる列があった。最初の列と、異なっていた列の要素数を持つ
+    UnequalSegments(usize, usize),
+    // ソートが中断された
+    Cancelled,
+}
+
+impl fmt::Display for SortError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortError::NotPowerOfTwo(len) =>
+                write!(f, "The length of x is not a power of two. (x.len(): {})", len),
+            SortError::TooLong(len) =>
+                write!(f, "The length of x exceeds the largest network. (x.len(): {})", len),
+            SortError::LengthMismatch(keys, values) =>
+                write!(f, "The lengths of keys and values differ. (keys.len(): {}, values.len(): {})",
+                       keys, values),
+            SortError::NotMultipleOfSegment(len, segment_len) =>
+                write!(f, "The length of x is not a multiple of the segment length. (x.len(): {}, segment_len: {})",
+                       len, segment_len),
+            SortError::UnequalSegments(expected, found) =>
+                write!(f, "The segments have different lengths. (expected: {}, found: {})", expected, found),
+            SortError::Cancelled =>
+                write!(f, "The sort was cancelled."),
+        }
+    }
+}
+
+impl Error for SortError {}
+
+// n未満で最大の2のべき乗を返す
+// 要素数が2のべき乗でないスライスをバイトニックマージするときに、比較する要素間の距離として使う
+fn greatest_power_of_two_less_than(n: usize) -> usize {
+    n.next_power_of_two() / 2
+}
+
+// キーだけをソートするときに、値の列の代わりに渡す()の列
+// ()は大きさを持たないので、メモリを確保せず、入れ替えても何もしない
+fn no_values(len: usize) -> Vec<()> {
+    vec![(); len]
+}
+
+// permの順にxの要素を並べ替える。perm[i]はi番目に来るべき要素の元のインデックス
+// 追加のメモリを使わずに入れ替えるため、permは作業領域として書き換えられる
+fn apply_permutation<T>(x: &mut [T], perm: &mut [usize]) {
+    for i in 0..x.len() {
+        // 元のインデックスがiより前なら、その要素はすでに別の位置へ移されている
+        // 移動先をたどって、現在の位置を見つける
+        let mut index = perm[i];
+        while index < i {
+            index = perm[index];
+        }
+        perm[i] = index;
+        x.swap(i, index);
+    }
+}
+
+
+// 各モジュールのソート結果がslice::sortと一致することを確かめるテスト
+// ネットワークの分割やしきい値の扱いを変えたときの退行を検出する
+#[cfg(test)]
+mod tests {
+    use super::{first, second, third, fourth, iterative, odd_even, networks};
+    use super::SortOrder::{self, *};
+    use super::SortError;
+    use super::fourth::SortConfig;
+    use super::utils::{new_u32_vec, new_vec, is_sorted_by_key, Pattern};
+    use proptest::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // 構造体を比較関数やキーでソートするテストに使う生徒
+    #[derive(Debug, PartialEq)]
+    pub(crate) struct Student {
+        pub(crate) first_name: String,
+        pub(crate) last_name: String,
+        pub(crate) age: u8,
+    }
+
+    impl Student {
+        pub(crate) fn new(first_name: &str, last_name: &str, age: u8) -> Self {
+            Self {
+                first_name: first_name.to_string(),
+                last_name: last_name.to_string(),
+                age,
+            }
+        }
+    }
+
+    // 年齢も名前もすべて異なる4人の生徒
+    pub(crate) fn students() -> Vec<Student> {
+        vec![
+            Student::new("Taro", "Yamada", 16),
+            Student::new("Hanako", "Yamada", 14),
+            Student::new("Kyoko", "Ito", 15),
+            Student::new("Ryosuke", "Hayashi", 17),
+        ]
+    }
+
+    fn first_names(x: &[&Student]) -> Vec<String> {
+        x.iter().map(|s| s.first_name.clone()).collect()
+    }
+
+    // slice::sortで作った期待値を返す
+    fn expected(x: &[u32], order: &SortOrder) -> Vec<u32> {
+        let mut y = x.to_vec();
+        y.sort();
+        if let Descending = *order {
+            y.reverse();
+        }
+        y
+    }
+
+    // 要素数が2のべき乗(1から1024まで)のベクタを生成する
+    // 値の範囲を狭めたものも混ぜて、等しい要素が多い入力も作る
+    fn power_of_two_vec() -> impl Strategy<Value = Vec<u32>> {
+        (0usize..=10, prop_oneof![Just(u32::MAX), Just(3u32)])
+            .prop_flat_map(|(exp, max)| prop::collection::vec(0..=max, 1 << exp))
+    }
+
+    fn arbitrary_len_vec() -> impl Strategy<Value = Vec<u32>> {
+        prop_oneof![
+            prop::collection::vec(any::<u32>(), 0..600),
+            prop::collection::vec(0u32..4, 0..600),
+        ]
+    }
+
+    // しきい値付近の要素数で使う、ソートが苦手になりやすい並び
+    fn adversarial_inputs(n: usize) -> Vec<Vec<u32>> {
+        let n32 = n as u32;
+        vec![
+            new_u32_vec(n),
+            (0..n32).collect(),                                   // 昇順
+            (0..n32).rev().collect(),                             // 降順
+            vec![7; n],                                           // すべて等しい
+            (0..n32).map(|i| i.min(n32 - i)).collect(),           // 山型
+            (0..n32).map(|i| i % 16).collect(),                   // のこぎり型
+            (0..n32).map(|i| if i % 2 == 0 { i } else { n32 - i }).collect(),
+            new_vec(n, Pattern::NearlySorted { swaps: 8 }, 1),
+            new_vec(n, Pattern::Zipf { unique: 64, exponent: 1.5 }, 1),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn power_of_two_matches_std(x in power_of_two_vec()) {
+            for order in &[Ascending, Descending] {
+                let expected = expected(&x, order);
+
+                let mut y = x.clone();
+                first::sort(&mut y, matches!(order, Ascending));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(second::sort(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(third::sort(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(fourth::sort(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(iterative::sort(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(odd_even::sort(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+            }
+        }
+
+        #[test]
+        fn arbitrary_length_matches_std(x in arbitrary_len_vec()) {
+            for order in &[Ascending, Descending] {
+                let expected = expected(&x, order);
+
+                let mut y = x.clone();
+                prop_assert_eq!(second::sort_arbitrary(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(third::sort_arbitrary(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(fourth::sort_arbitrary(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                prop_assert_eq!(odd_even::sort_arbitrary(&mut y, order), Ok(()));
+                prop_assert_eq!(&y, &expected);
+
+                let mut y = x.clone();
+                let result = networks::sort(&mut y, order);
+                if x.len() <= networks::MAX_LEN {
+                    prop_assert_eq!(result, Ok(()));
+                    prop_assert_eq!(&y, &expected);
+                } else {
+                    prop_assert_eq!(result, Err(SortError::TooLong(x.len())));
+                }
+            }
+        }
+
+        // しきい値と入れ子の深さを小さな値で変化させ、
+        // 並列処理と順次処理が入れ替わる境界をすべての段で通るようにする
+        #[test]
+        fn fourth_matches_std_for_any_threshold(x in power_of_two_vec(),
+                                                threshold in 1usize..=16,
+                                                max_depth in 0usize..=6) {
+            let config = SortConfig::new().threshold(threshold).max_depth(max_depth);
+            for order in &[Ascending, Descending] {
+                let mut y = x.clone();
+                prop_assert_eq!(fourth::sort_with_config(&mut y, order, &config), Ok(()));
+                prop_assert_eq!(y, expected(&x, order));
+            }
+        }
+
+        // キーの並びはslice::sortと一致し、値はソート前と同じキーと組になっている
+        #[test]
+        fn sort_pairs_matches_std(x in power_of_two_vec()) {
+            for order in &[Ascending, Descending] {
+                let expected = expected(&x, order);
+
+                let (mut keys, mut values) = (x.clone(), (0..x.len()).collect::<Vec<_>>());
+                prop_assert_eq!(third::sort_pairs(&mut keys, &mut values, order), Ok(()));
+                prop_assert_eq!(&keys, &expected);
+                prop_assert!(values.iter().zip(&keys).all(|(&i, &k)| x[i] == k));
+
+                let mut keys = x.clone();
+                let perm = fourth::argsort(&mut keys, order).unwrap();
+                prop_assert_eq!(&keys, &expected);
+                prop_assert!(perm.iter().zip(&keys).all(|(&i, &k)| x[i] == k));
+            }
+        }
+
+        // 部分ソートの先頭k要素は、全体をソートした結果の先頭k要素と一致する
+        #[test]
+        fn partial_sort_matches_std(x in arbitrary_len_vec(), k in 0usize..700) {
+            let k_clamped = k.min(x.len());
+            for order in &[Ascending, Descending] {
+                let mut y = x.clone();
+                prop_assert_eq!(fourth::partial_sort(&mut y, k, order), Ok(()));
+                prop_assert_eq!(&y[..k_clamped], &expected(&x, order)[..k_clamped]);
+            }
+        }
+
+        // 任意の長さのソート済みの列2つをマージした結果は、連結してソートした結果と一致する
+        #[test]
+        fn merge_matches_std(a in arbitrary_len_vec(), b in arbitrary_len_vec()) {
+            for order in &[Ascending, Descending] {
+                let (a, b) = (expected(&a, order), expected(&b, order));
+                let mut both = a.clone();
+                both.extend(&b);
+                let both = expected(&both, order);
+
+                prop_assert_eq!(third::merge(&a, &b, order), both.clone());
+                prop_assert_eq!(fourth::merge(&a, &b, order), both);
+            }
+        }
+    }
+
+    #[test]
+    fn power_of_two_around_parallel_threshold() {
+        // 既定のしきい値は4096。分割後の要素数がしきい値の前後になる要素数を試す
+        for &n in &[4096, 8192, 16384] {
+            for x in adversarial_inputs(n) {
+                for order in &[Ascending, Descending] {
+                    let expected = expected(&x, order);
+
+                    let mut y = x.clone();
+                    assert_eq!(third::sort(&mut y, order), Ok(()));
+                    assert_eq!(y, expected);
+
+                    let mut y = x.clone();
+                    assert_eq!(fourth::sort(&mut y, order), Ok(()));
+                    assert_eq!(y, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arbitrary_length_around_parallel_threshold() {
+        for &n in &[8191, 8193, 12289] {
+            for x in adversarial_inputs(n) {
+                for order in &[Ascending, Descending] {
+                    let mut y = x.clone();
+                    assert_eq!(fourth::sort_arbitrary(&mut y, order), Ok(()));
+                    assert_eq!(y, expected(&x, order));
+                }
+            }
+        }
+    }
+
+    // 以降はthirdとfourthで同じ結果になることを、生徒の列で確かめる
+
+    #[test]
+    fn sort_students_by_age_arbitrary_length() {
+        let s = students();
+        let by_age = |a: &&Student, b: &&Student| a.age.cmp(&b.age);
+
+        // 3要素なのでsort_byではエラーになる
+        let mut x: Vec<&Student> = s[..3].iter().collect();
+        assert!(third::sort_by(&mut x, &by_age).is_err());
+        assert!(fourth::sort_by(&mut x, &by_age).is_err());
+
+        assert_eq!(third::sort_by_arbitrary(&mut x, &by_age), Ok(()));
+        assert_eq!(first_names(&x), vec!["Hanako", "Kyoko", "Taro"]);
+
+        let mut x: Vec<&Student> = s[..3].iter().collect();
+        assert_eq!(fourth::sort_by_arbitrary(&mut x, &by_age), Ok(()));
+        assert_eq!(first_names(&x), vec!["Hanako", "Kyoko", "Taro"]);
+    }
+
+    #[test]
+    // 年齢をキーにして昇順にソートする
+    fn sort_students_by_age_key() {
+        let s = students();
+        let expected = vec!["Hanako", "Kyoko", "Taro", "Ryosuke"];
+
+        let mut x: Vec<&Student> = s.iter().collect();
+        assert_eq!(third::sort_by_key(&mut x, &|s| s.age), Ok(()));
+        assert_eq!(first_names(&x), expected);
+
+        let mut x: Vec<&Student> = s.iter().collect();
+        assert_eq!(fourth::sort_by_key(&mut x, &|s| s.age), Ok(()));
+        assert_eq!(first_names(&x), expected);
+    }
+
+    #[test]
+    // 名前をキーにして昇順にソートし、キーが要素ごとに一度だけ計算されることを確認する
+    fn sort_students_by_cached_name_key() {
+        let s = students();
+        let expected = vec!["Ryosuke", "Kyoko", "Hanako", "Taro"];
+
+        // fourthではキーの計算が複数のスレッドから呼ばれるので、アトミックな変数で数える
+        let calls = AtomicUsize::new(0);
+        let key = |s: &&Student| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            (s.last_name.to_lowercase(), s.first_name.to_lowercase())
+        };
+
+        let mut x: Vec<&Student> = s.iter().collect();
+        assert_eq!(third::sort_by_cached_key(&mut x, &key), Ok(()));
+        assert_eq!(first_names(&x), expected);
+        assert_eq!(calls.swap(0, Ordering::SeqCst), 4);
+
+        let mut x: Vec<&Student> = s.iter().collect();
+        assert_eq!(fourth::sort_by_cached_key(&mut x, &key), Ok(()));
+        assert_eq!(first_names(&x), expected);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    // 年齢で安定ソートする。同じ年齢の生徒はソート前の順序のまま並ぶ
+    fn sort_students_by_age_stable() {
+        let mut s = students();
+        s.push(Student::new("Jiro", "Sato", 15));
+        s.push(Student::new("Sakura", "Kato", 16));
+        s.push(Student::new("Ken", "Abe", 14));
+        s.push(Student::new("Yui", "Mori", 15));
+        let expected = vec!["Hanako", "Ken", "Kyoko", "Jiro", "Yui", "Taro", "Sakura", "Ryosuke"];
+
+        let by_age = |a: &&Student, b: &&Student| a.age.cmp(&b.age);
+        let by_name = |a: &&Student, b: &&Student| a.last_name.cmp(&b.last_name)
+            .then_with(|| a.first_name.cmp(&b.first_name));
+        let mut by_age_then_name: Vec<&Student> = s.iter().collect();
+        by_age_then_name.sort_by(|a, b| by_age(a, b).then_with(|| by_name(a, b)));
+
+        let mut x: Vec<&Student> = s.iter().collect();
+        assert_eq!(third::sort_by_stable(&mut x, &by_age), Ok(()));
+        assert_eq!(first_names(&x), expected);
+        // 名前でソートしてから年齢で安定ソートすると、年齢・名前の順に並ぶ
+        assert_eq!(third::sort_by_stable(&mut x, &by_name), Ok(()));
+        assert_eq!(third::sort_by_stable(&mut x, &by_age), Ok(()));
+        assert_eq!(x, by_age_then_name);
+
+        let mut x: Vec<&Student> = s.iter().collect();
+        assert_eq!(fourth::sort_by_stable(&mut x, &by_age), Ok(()));
+        assert_eq!(first_names(&x), expected);
+        assert_eq!(fourth::sort_by_stable(&mut x, &by_name), Ok(()));
+        assert_eq!(fourth::sort_by_stable(&mut x, &by_age), Ok(()));
+        assert_eq!(x, by_age_then_name);
+    }
+
+    #[test]
+    fn merge_students_by_age() {
+        let s = students();
+        let by_age = |a: &&Student, b: &&Student| a.age.cmp(&b.age);
+        let a = vec![&s[1], &s[0]];
+        let b = vec![&s[2], &s[3]];
+        let expected = vec!["Hanako", "Kyoko", "Taro", "Ryosuke"];
+        assert_eq!(first_names(&third::merge_by(&a, &b, &by_age)), expected);
+        assert_eq!(first_names(&fourth::merge_by(&a, &b, &by_age)), expected);
+    }
+
+    #[test]
+    fn sort_pairs_students_by_age() {
+        // 年齢をキーにして、生徒を値として一緒に並べ替える
+        let mut ages: Vec<u8> = vec![16, 14, 15, 17];
+        let mut x = students();
+        assert_eq!(third::sort_pairs(&mut ages, &mut x, &Ascending), Ok(()));
+        assert!(x.iter().zip(&ages).all(|(s, &age)| s.age == age));
+        assert!(is_sorted_by_key(&x, &|s: &Student| s.age));
+
+        let mut ages: Vec<u8> = vec![16, 14, 15, 17];
+        let mut x = students();
+        assert_eq!(fourth::sort_pairs(&mut ages, &mut x, &Descending), Ok(()));
+        assert!(x.iter().zip(&ages).all(|(s, &age)| s.age == age));
+        assert!(is_sorted_by_key(&x, &|s: &Student| std::cmp::Reverse(s.age)));
+    }
+}
@@ -0,0 +1,345 @@
+// バイトニックソートでテキストの行をソートするコマンド
+//
+//   bitonic-sorter2 [OPTIONS] [FILE]...
+//
+// FILEを指定しないか、FILEが"-"のときは標準入力から読み込む
+// ソート結果は標準出力に書き出す
+
+use bitonic_sorter2::{second, third, fourth, SortOrder};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::process;
+
+const USAGE: &str = "\
+Usage: bitonic-sorter2 [OPTIONS] [FILE]...
+
+Sort lines of text with a bitonic sorting network.
+With no FILE, or when FILE is -, read standard input.
+
+Options:
+  --algorithm <second|third|fourth>  sorter to use (default: fourth)
+  --order <asc|desc>                 sort order (default: asc)
+  --numeric                          compare keys as numbers
+  --key <field>                      sort by the whitespace-separated field (1-based)
+  --threads <N>                      number of threads (fourth only)
+  --check                            check whether the input is sorted; do not sort
+  --help                             print this help";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Algorithm {
+    Second,
+    Third,
+    Fourth,
+}
+
+// コマンドラインオプション
+#[derive(Debug, PartialEq)]
+struct Options {
+    algorithm: Algorithm,
+    ascending: bool,
+    numeric: bool,
+    key: Option<usize>,
+    threads: Option<usize>,
+    check: bool,
+    files: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            algorithm: Algorithm::Fourth,
+            ascending: true,
+            numeric: false,
+            key: None,
+            threads: None,
+            check: false,
+            files: Vec::new(),
+        }
+    }
+}
+
+// ソートに使うキー。--numericなら数値、そうでなければ文字列として比較する
+#[derive(Debug)]
+enum Key {
+    Text(String),
+    Number(f64),
+}
+
+// 入力の1行とそのキー
+#[derive(Debug)]
+struct Line {
+    key: Key,
+    text: String,
+}
+
+// キーで比較し、キーが等しければ行全体で比較する
+// 比較結果が一意に決まるので、不安定なバイトニックソートでも出力が毎回同じになる
+impl Ord for Line {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let key_order = match (&self.key, &other.key) {
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::Text(a), Key::Text(b)) => a.cmp(b),
+            // 同じ入力のキーはどれも同じ種類になる
+            (Key::Number(_), Key::Text(_)) => Ordering::Less,
+            (Key::Text(_), Key::Number(_)) => Ordering::Greater,
+        };
+        key_order.then_with(|| self.text.cmp(&other.text))
+    }
+}
+
+impl PartialOrd for Line {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Line {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Line {}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    match parse_args(&args).and_then(|options| run(&options)) {
+        Ok(true) => {}
+        // --checkで入力がソートされていなかった
+        Ok(false) => process::exit(1),
+        Err(message) => {
+            eprintln!("bitonic-sorter2: {}", message);
+            process::exit(2);
+        }
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        // 値をとるオプションは、次の引数を値として取り出す
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| format!("option '{}' requires a value", name))
+        };
+
+        match arg.as_str() {
+            "--algorithm" => {
+                options.algorithm = match value(arg)?.as_str() {
+                    "second" => Algorithm::Second,
+                    "third" => Algorithm::Third,
+                    "fourth" => Algorithm::Fourth,
+                    other => return Err(format!("unknown algorithm '{}'", other)),
+                }
+            }
+            "--order" => {
+                options.ascending = match value(arg)?.as_str() {
+                    "asc" => true,
+                    "desc" => false,
+                    other => return Err(format!("unknown order '{}'", other)),
+                }
+            }
+            "--key" => {
+                let field = value(arg)?;
+                match field.parse() {
+                    Ok(n) if n > 0 => options.key = Some(n),
+                    _ => return Err(format!("invalid field number '{}'", field)),
+                }
+            }
+            "--threads" => {
+                let threads = value(arg)?;
+                match threads.parse() {
+                    Ok(n) if n > 0 => options.threads = Some(n),
+                    _ => return Err(format!("invalid number of threads '{}'", threads)),
+                }
+            }
+            "--numeric" => options.numeric = true,
+            "--check" => options.check = true,
+            "-" => options.files.push(arg.clone()),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ => options.files.push(arg.clone()),
+        }
+    }
+
+    // スレッド数を指定できるのはfourthだけなので、他のアルゴリズムとの組み合わせは黙って無視せずエラーにする
+    if options.threads.is_some() && options.algorithm != Algorithm::Fourth {
+        return Err("option '--threads' requires '--algorithm fourth'".to_string());
+    }
+    Ok(options)
+}
+
+// 入力を読み込んでソートし、結果を書き出す
+// --checkのときは、入力がソートされていればtrueを返す
+fn run(options: &Options) -> Result<bool, String> {
+    let mut lines = read_lines(options)?;
+
+    let order = if options.ascending { SortOrder::Ascending } else { SortOrder::Descending };
+    if options.check {
+        return Ok(check(&lines, &order));
+    }
+
+    // 行数は2のべき乗とは限らないので、要素数に制約のないモードを使う
+    let result = match options.algorithm {
+        Algorithm::Second => second::sort_arbitrary(&mut lines, &order),
+        Algorithm::Third => third::sort_arbitrary(&mut lines, &order),
+        Algorithm::Fourth => match options.threads {
+            Some(threads) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                pool.install(|| fourth::sort_arbitrary(&mut lines, &order))
+            }
+            None => fourth::sort_arbitrary(&mut lines, &order),
+        },
+    };
+    result.map_err(|e| e.to_string())?;
+
+    let stdout = io::stdout();
+    let mut writer = BufWriter::new(stdout.lock());
+    for line in &lines {
+        writeln!(writer, "{}", line.text).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+// すべての入力ファイルから行を読み込み、キーを取り出す
+fn read_lines(options: &Options) -> Result<Vec<Line>, String> {
+    let mut files = options.files.clone();
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut lines = Vec::new();
+    for file in &files {
+        let reader: Box<dyn BufRead> = if file == "-" {
+            Box::new(BufReader::new(io::stdin()))
+        } else {
+            let f = File::open(file).map_err(|e| format!("{}: {}", file, e))?;
+            Box::new(BufReader::new(f))
+        };
+
+        for (i, text) in reader.lines().enumerate() {
+            let text = text.map_err(|e| format!("{}: {}", file, e))?;
+            let key = parse_key(&text, options)
+                .map_err(|e| format!("{}:{}: {}", file, i + 1, e))?;
+            lines.push(Line { key, text });
+        }
+    }
+    Ok(lines)
+}
+
+// 行からキーを取り出す。--keyがなければ行全体をキーにする
+fn parse_key(text: &str, options: &Options) -> Result<Key, String> {
+    let field = match options.key {
+        Some(n) => text.split_whitespace().nth(n - 1).unwrap_or(""),
+        None => text.trim(),
+    };
+
+    if options.numeric {
+        field.parse()
+            .map(Key::Number)
+            .map_err(|_| format!("'{}' is not a number", field))
+    } else {
+        Ok(Key::Text(field.to_string()))
+    }
+}
+
+// 入力がorderの順に並んでいるか調べ、最初に順序が崩れている行を報告する
+fn check(lines: &[Line], order: &SortOrder) -> bool {
+    let out_of_order = match *order {
+        SortOrder::Ascending => Ordering::Greater,
+        SortOrder::Descending => Ordering::Less,
+    };
+    match lines.windows(2).position(|pair| pair[0].cmp(&pair[1]) == out_of_order) {
+        Some(i) => {
+            eprintln!("bitonic-sorter2: disorder at line {}: {}", i + 2, lines[i + 1].text);
+            false
+        }
+        None => true,
+    }
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{check, parse_args, parse_key, Algorithm, Key, Line, Options};
+    use bitonic_sorter2::SortOrder::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|arg| arg.to_string()).collect()
+    }
+
+    fn line(key: Key, text: &str) -> Line {
+        Line { key, text: text.to_string() }
+    }
+
+    #[test]
+    fn parse_all_options() {
+        let options = parse_args(&args(
+            "--algorithm fourth --order desc --numeric --key 2 --threads 4 --check a.txt -"
+        )).unwrap();
+        assert_eq!(options, Options {
+            algorithm: Algorithm::Fourth,
+            ascending: false,
+            numeric: true,
+            key: Some(2),
+            threads: Some(4),
+            check: true,
+            files: vec!["a.txt".to_string(), "-".to_string()],
+        });
+
+        assert_eq!(parse_args(&[]).unwrap(), Options::default());
+    }
+
+    #[test]
+    fn parse_invalid_options() {
+        assert!(parse_args(&args("--algorithm fifth")).is_err());
+        assert!(parse_args(&args("--order up")).is_err());
+        assert!(parse_args(&args("--key 0")).is_err());
+        assert!(parse_args(&args("--threads")).is_err());
+        assert!(parse_args(&args("--reverse")).is_err());
+        // --threadsはfourth以外では使えない
+        assert!(parse_args(&args("--algorithm second --threads 2")).is_err());
+        assert!(parse_args(&args("--threads 2 --algorithm third")).is_err());
+    }
+
+    #[test]
+    fn keys_from_fields() {
+        let options = parse_args(&args("--numeric --key 2")).unwrap();
+        match parse_key("taro 16 yamada", &options) {
+            Ok(Key::Number(n)) => assert_eq!(n, 16.0),
+            other => panic!("unexpected key: {:?}", other),
+        }
+        assert!(parse_key("taro", &options).is_err());
+
+        let options = parse_args(&args("--key 3")).unwrap();
+        match parse_key("taro 16 yamada", &options) {
+            Ok(Key::Text(s)) => assert_eq!(s, "yamada"),
+            other => panic!("unexpected key: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn numeric_keys_sort_as_numbers() {
+        let mut lines = vec![
+            line(Key::Number(10.0), "10"),
+            line(Key::Number(9.0), "9"),
+            line(Key::Number(-1.5), "-1.5"),
+        ];
+        assert!(!check(&lines, &Ascending));
+        assert_eq!(bitonic_sorter2::third::sort_arbitrary(&mut lines, &Ascending), Ok(()));
+        let texts: Vec<_> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, vec!["-1.5", "9", "10"]);
+        assert!(check(&lines, &Ascending));
+        assert!(!check(&lines, &Descending));
+    }
+}
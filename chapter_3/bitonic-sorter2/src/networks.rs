@@ -0,0 +1,297 @@
+use super::{SortError, no_values};
+use super::SortOrder;
+use super::utils::check_sorted_by;
+use std::cmp::Ordering;
+
+// 16要素までの固定長のソーティングネットワーク
+// どれも比較器の数が最小か、知られている中で最小のもの(13〜15要素)
+// 比較器(i, j)はi < jで、x[i]に小さい方、x[j]に大きい方を置く
+//
+// 比較器の数はバイトニックソートより少ない(16要素なら80個に対して60個)ので、
+// thirdとfourthでは再帰の末端でこれらのネットワークを使う
+
+// ネットワークでソートできる最大の要素数
+pub const MAX_LEN: usize = 16;
+
+// 2要素: 比較器1個、1段
+const NETWORK_2: [(usize, usize); 1] = [
+    (0, 1),
+];
+
+// 3要素: 比較器3個、3段
+const NETWORK_3: [(usize, usize); 3] = [
+    (0, 2),
+    (0, 1),
+    (1, 2),
+];
+
+// 4要素: 比較器5個、3段
+const NETWORK_4: [(usize, usize); 5] = [
+    (0, 2), (1, 3),
+    (0, 1), (2, 3),
+    (1, 2),
+];
+
+// 5要素: 比較器9個、5段
+const NETWORK_5: [(usize, usize); 9] = [
+    (0, 3), (1, 4),
+    (0, 2), (1, 3),
+    (0, 1), (2, 4),
+    (1, 2), (3, 4),
+    (2, 3),
+];
+
+// 6要素: 比較器12個、5段
+const NETWORK_6: [(usize, usize); 12] = [
+    (0, 5), (1, 3), (2, 4),
+    (1, 2), (3, 4),
+    (0, 3), (2, 5),
+    (0, 1), (2, 3), (4, 5),
+    (1, 2), (3, 4),
+];
+
+// 7要素: 比較器16個、6段
+const NETWORK_7: [(usize, usize); 16] = [
+    (0, 6), (2, 3), (4, 5),
+    (0, 2), (1, 4), (3, 6),
+    (0, 1), (2, 5), (3, 4),
+    (1, 2), (4, 6),
+    (2, 3), (4, 5),
+    (1, 2), (3, 4), (5, 6),
+];
+
+// 8要素: 比較器19個、6段
+const NETWORK_8: [(usize, usize); 19] = [
+    (0, 2), (1, 3), (4, 6), (5, 7),
+    (0, 4), (1, 5), (2, 6), (3, 7),
+    (0, 1), (2, 3), (4, 5), (6, 7),
+    (2, 4), (3, 5),
+    (1, 4), (3, 6),
+    (1, 2), (3, 4), (5, 6),
+];
+
+// 9要素: 比較器25個、7段
+const NETWORK_9: [(usize, usize); 25] = [
+    (0, 3), (1, 7), (2, 5), (4, 8),
+    (0, 7), (2, 4), (3, 8), (5, 6),
+    (0, 2), (1, 3), (4, 5), (7, 8),
+    (1, 4), (3, 6), (5, 7),
+    (0, 1), (2, 4), (3, 5), (6, 8),
+    (2, 3), (4, 5), (6, 7),
+    (1, 2), (3, 4), (5, 6),
+];
+
+// 10要素: 比較器29個、8段
+const NETWORK_10: [(usize, usize); 29] = [
+    (0, 8), (1, 9), (2, 7), (3, 5), (4, 6),
+    (0, 2), (1, 4), (5, 8), (7, 9),
+    (0, 3), (2, 4), (5, 7), (6, 9),
+    (0, 1), (3, 6), (8, 9),
+    (1, 5), (2, 3), (4, 8), (6, 7),
+    (1, 2), (3, 5), (4, 6), (7, 8),
+    (2, 3), (4, 5), (6, 7),
+    (3, 4), (5, 6),
+];
+
+// 11要素: 比較器35個、9段
+const NETWORK_11: [(usize, usize); 35] = [
+    (0, 6), (1, 5), (2, 10), (3, 9), (4, 8),
+    (1, 4), (2, 3), (5, 8), (6, 7), (9, 10),
+    (0, 5), (4, 9), (8, 10),
+    (0, 1), (3, 5), (4, 6), (7, 10), (8, 9),
+    (0, 3), (2, 4), (5, 7), (6, 9),
+    (0, 2), (1, 4), (5, 8), (7, 9),
+    (1, 2), (3, 4), (5, 6), (7, 8),
+    (3, 5), (4, 6),
+    (2, 3), (4, 5), (6, 7),
+];
+
+// 12要素: 比較器39個、9段
+const NETWORK_12: [(usize, usize); 39] = [
+    (0, 8), (1, 7), (2, 6), (3, 11), (4, 10), (5, 9),
+    (0, 1), (2, 5), (3, 4), (6, 9), (7, 8), (10, 11),
+    (0, 2), (1, 6), (5, 10), (9, 11),
+    (0, 3), (1, 2), (4, 6), (5, 7), (8, 11), (9, 10),
+    (1, 4), (3, 5), (6, 8), (7, 10),
+    (1, 3), (2, 5), (6, 9), (8, 10),
+    (2, 3), (4, 5), (6, 7), (8, 9),
+    (4, 6), (5, 7),
+    (3, 4), (5, 6), (7, 8),
+];
+
+// 13要素: 比較器45個、10段
+const NETWORK_13: [(usize, usize); 45] = [
+    (0, 12), (1, 10), (2, 9), (3, 7), (5, 11), (6, 8),
+    (1, 6), (2, 3), (4, 11), (7, 9), (8, 10),
+    (0, 4), (1, 2), (3, 6), (7, 8), (9, 10), (11, 12),
+    (4, 6), (5, 9), (8, 11), (10, 12),
+    (0, 5), (3, 8), (4, 7), (6, 11), (9, 10),
+    (0, 1), (2, 5), (6, 9), (7, 8), (10, 11),
+    (1, 3), (2, 4), (5, 6), (9, 10),
+    (1, 2), (3, 4), (5, 7), (6, 8),
+    (2, 3), (4, 5), (6, 7), (8, 9),
+    (3, 4), (5, 6),
+];
+
+// 14要素: 比較器51個、10段
+const NETWORK_14: [(usize, usize); 51] = [
+    (0, 13), (1, 12), (2, 6), (3, 4), (5, 9), (7, 8),
+    (0, 7), (1, 2), (4, 11), (6, 12), (8, 13), (9, 10),
+    (0, 1), (2, 3), (4, 6), (5, 7), (8, 9), (10, 11), (12, 13),
+    (2, 8), (3, 9), (4, 5), (6, 7), (10, 12), (11, 13),
+    (1, 10), (2, 4), (3, 5), (6, 8), (7, 9), (11, 12),
+    (0, 4), (3, 6), (5, 8), (7, 11), (9, 12),
+    (0, 2), (1, 4), (7, 10), (9, 11),
+    (1, 3), (4, 6), (5, 7), (8, 10),
+    (1, 2), (3, 4), (5, 6), (7, 8), (9, 10),
+    (4, 5), (6, 7),
+];
+
+// 15要素: 比較器56個、10段
+const NETWORK_15: [(usize, usize); 56] = [
+    (0, 11), (1, 14), (2, 13), (3, 7), (4, 5), (6, 10), (8, 9),
+    (0, 6), (1, 8), (2, 3), (5, 12), (7, 13), (9, 14), (10, 11),
+    (1, 2), (3, 4), (5, 7), (6, 8), (9, 10), (11, 12), (13, 14),
+    (0, 2), (3, 9), (4, 10), (5, 6), (7, 8), (11, 13), (12, 14),
+    (0, 1), (2, 11), (3, 5), (4, 6), (7, 9), (8, 10), (12, 13),
+    (0, 3), (1, 5), (4, 7), (6, 9), (8, 12), (10, 13),
+    (1, 3), (2, 5), (8, 11), (10, 12),
+    (2, 4), (5, 7), (6, 8), (9, 11),
+    (2, 3), (4, 5), (6, 7), (8, 9), (10, 11),
+    (5, 6), (7, 8),
+];
+
+// 16要素: 比較器60個、10段
+const NETWORK_16: [(usize, usize); 60] = [
+    (0, 13), (1, 12), (2, 15), (3, 14), (4, 8), (5, 6), (7, 11), (9, 10),
+    (0, 5), (1, 7), (2, 9), (3, 4), (6, 13), (8, 14), (10, 15), (11, 12),
+    (0, 1), (2, 3), (4, 5), (6, 8), (7, 9), (10, 11), (12, 13), (14, 15),
+    (0, 2), (1, 3), (4, 10), (5, 11), (6, 7), (8, 9), (12, 14), (13, 15),
+    (1, 2), (3, 12), (4, 6), (5, 7), (8, 10), (9, 11), (13, 14),
+    (1, 4), (2, 6), (5, 8), (7, 10), (9, 13), (11, 14),
+    (2, 4), (3, 6), (9, 12), (11, 13),
+    (3, 5), (6, 8), (7, 9), (10, 12),
+    (3, 4), (5, 6), (7, 8), (9, 10), (11, 12),
+    (6, 7), (8, 9),
+];
+
+// 要素数nのスライスをソートするネットワークを返す
+// 0要素と1要素は比較器のない空のネットワークになる
+pub fn network(n: usize) -> Option<&'static [(usize, usize)]> {
+    let network: &'static [(usize, usize)] = match n {
+        0 | 1 => &[],
+        2 => &NETWORK_2,
+        3 => &NETWORK_3,
+        4 => &NETWORK_4,
+        5 => &NETWORK_5,
+        6 => &NETWORK_6,
+        7 => &NETWORK_7,
+        8 => &NETWORK_8,
+        9 => &NETWORK_9,
+        10 => &NETWORK_10,
+        11 => &NETWORK_11,
+        12 => &NETWORK_12,
+        13 => &NETWORK_13,
+        14 => &NETWORK_14,
+        15 => &NETWORK_15,
+        16 => &NETWORK_16,
+        _ => return None,
+    };
+    Some(network)
+}
+
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where F: Fn(&T, &T) -> Ordering
+{
+    if x.len() <= MAX_LEN {
+        sort_small(x, &mut no_values(x.len()), true, comparator);
+        check_sorted_by(x, comparator);
+        Ok(())
+    } else {
+        Err(SortError::TooLong(x.len()))
+    }
+}
+
+// forwardがtrueならcomparatorの順、falseなら逆順にソートする
+// thirdとfourthのdo_sortが、再帰の末端で呼び出す。xの要素数はMAX_LEN以下であること
+// xの要素を入れ替えるときは、valuesの同じ位置の要素も入れ替える。交換した回数を返す
+pub(crate) fn sort_small<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F) -> usize
+    where F: Fn(&T, &T) -> Ordering
+{
+    let swap_condition = if forward {
+        Ordering::Greater
+    } else {
+        Ordering::Less
+    };
+    let network = network(x.len()).expect("too many elements for a fixed-size network");
+    let mut swaps = 0;
+    for &(i, j) in network {
+        if comparator(&x[i], &x[j]) == swap_condition {
+            x.swap(i, j);
+            values.swap(i, j);
+            swaps += 1;
+        }
+    }
+    swaps
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{network, sort, sort_by, MAX_LEN};
+    use crate::SortError;
+    use crate::SortOrder::*;
+
+    // 0-1原理: 0と1だけからなる2^n通りの入力をすべてソートできれば、
+    // どんな入力でもソートできるネットワークである
+    #[test]
+    fn sorts_all_zero_one_inputs() {
+        for n in 0..=MAX_LEN {
+            let network = network(n).unwrap();
+            for bits in 0u32..(1 << n) {
+                let mut x: Vec<u32> = (0..n).map(|i| (bits >> i) & 1).collect();
+                for &(i, j) in network {
+                    assert!(i < j && j < n);
+                    if x[i] > x[j] {
+                        x.swap(i, j);
+                    }
+                }
+                assert!(x.windows(2).all(|w| w[0] <= w[1]), "n = {}, input = {:b}", n, bits);
+            }
+        }
+    }
+
+    #[test]
+    fn comparator_counts() {
+        let counts: Vec<_> = (2..=MAX_LEN).map(|n| network(n).unwrap().len()).collect();
+        assert_eq!(counts, vec![1, 3, 5, 9, 12, 16, 19, 25, 29, 35, 39, 45, 51, 56, 60]);
+    }
+
+    #[test]
+    fn sort_u32_descending() {
+        let mut x = vec![10, 30, 11, 20, 4, 330, 21, 110, 7];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 7, 4]);
+    }
+
+    #[test]
+    fn sort_str_by_len() {
+        let mut x = vec!["ccc", "a", "dddd", "bb"];
+        assert_eq!(sort_by(&mut x, &|a: &&str, b: &&str| a.len().cmp(&b.len())), Ok(()));
+        assert_eq!(x, vec!["a", "bb", "ccc", "dddd"]);
+    }
+
+    #[test]
+    fn sort_too_long() {
+        let mut x = vec![0u32; MAX_LEN + 1];
+        assert_eq!(sort(&mut x, &Ascending), Err(SortError::TooLong(17)));
+        assert!(network(MAX_LEN + 1).is_none());
+    }
+}
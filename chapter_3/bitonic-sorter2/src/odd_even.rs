@@ -0,0 +1,136 @@
+use super::{SortOrder, SortError};
+use super::utils::check_sorted_by;
+use std::cmp::Ordering;
+
+// Batcherの奇偶マージソート
+// バイトニックソートと同じく比較の順序が入力によらないソーティングネットワークで、
+// 比較器の数はバイトニックソートより少ない(1024要素なら24,063個に対して28,160個)
+//
+// iterativeと同じく、再帰を使わずに段ごとのループで比較器を並べる
+// どの比較器も小さい方を前に置くので、要素数が2のべき乗でないときは、
+// 末尾に仮想的な最大の要素があるものとして、範囲外の要素との比較器を省くだけでよい
+
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
+    }
+}
+
+// 要素数が2のべき乗でなくてもソートできるモード
+pub fn sort_arbitrary<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by_arbitrary(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by_arbitrary(x, &|a, b| b.cmp(a)),
+    }
+}
+
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where F: Fn(&T, &T) -> Ordering
+{
+    if x.len().is_power_of_two() {
+        do_sort(x, comparator);
+        check_sorted_by(x, comparator);
+        Ok(())
+    } else {
+        Err(SortError::NotPowerOfTwo(x.len()))
+    }
+}
+
+// 要素数が2のべき乗でなくてもソートできるsort_by
+pub fn sort_by_arbitrary<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where F: Fn(&T, &T) -> Ordering
+{
+    do_sort(x, comparator);
+    check_sorted_by(x, comparator);
+    Ok(())
+}
+
+pub(crate) fn do_sort<T, F>(x: &mut [T], comparator: &F)
+    where F: Fn(&T, &T) -> Ordering
+{
+    let len = x.len();
+    // 大きさpartの2つのソート済みの列を、大きさ2 * partの列にマージしていく
+    let mut part = 1;
+    while part < len {
+        // 距離stepだけ離れた要素を比較する。stepはpartから1まで半分ずつ小さくなる
+        let mut step = part;
+        while step > 0 {
+            let mut start = step % part;
+            while start + step < len {
+                for i in start..(start + step).min(len - step) {
+                    // 比較する2要素が同じ大きさ2 * partのブロックにあるときだけ比較する
+                    if i / (2 * part) == (i + step) / (2 * part)
+                        && comparator(&x[i], &x[i + step]) == Ordering::Greater {
+                        x.swap(i, i + step);
+                    }
+                }
+                start += 2 * step;
+            }
+            step /= 2;
+        }
+        part *= 2;
+    }
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{do_sort, sort, sort_arbitrary};
+    use crate::SortError;
+    use crate::SortOrder::*;
+    use crate::utils::{is_sorted_ascending, is_sorted_descending, new_u32_vec};
+    use std::cell::Cell;
+
+    #[test]
+    fn sort_u32_ascending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x, vec![4, 10, 11, 20, 21, 30, 110, 330]);
+    }
+
+    #[test]
+    fn sort_u32_descending() {
+        let mut x: Vec<u32> = vec![10, 30, 11, 20, 4, 330, 21, 110];
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert_eq!(x, vec![330, 110, 30, 21, 20, 11, 10, 4]);
+    }
+
+    #[test]
+    fn sort_u32_large() {
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert!(is_sorted_ascending(&x));
+
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert!(is_sorted_descending(&x));
+    }
+
+    #[test]
+    fn sort_u32_arbitrary_length() {
+        for n in 0..300 {
+            let mut x = new_u32_vec(n);
+            assert_eq!(sort_arbitrary(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x));
+        }
+    }
+
+    // 比較の回数が、奇偶マージソートの比較器の数と一致する
+    // 2^k要素の比較器の数は(k^2 - k + 4) * 2^(k - 2) - 1個
+    #[test]
+    fn comparisons_match_network_size() {
+        for k in 2..=10 {
+            let count = Cell::new(0usize);
+            let mut x = new_u32_vec(1 << k);
+            do_sort(&mut x, &|a: &u32, b: &u32| { count.set(count.get() + 1); a.cmp(b) });
+            assert_eq!(count.get(), (k * k - k + 4) * (1 << (k - 2)) - 1);
+        }
+    }
+
+    #[test]
+    fn sort_not_power_of_two() {
+        let mut x: Vec<u32> = vec![10, 30, 11];
+        assert_eq!(sort(&mut x, &Ascending), Err(SortError::NotPowerOfTwo(3)));
+    }
+}
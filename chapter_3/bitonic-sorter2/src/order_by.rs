@@ -0,0 +1,305 @@
+use super::SortOrder;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+// 複数のキーを組み合わせた比較関数を作る
+// then_withを手で書く代わりに、キーとその順序を並べて比較関数を組み立てる
+//
+//   let order = OrderBy::key_ref(|s: &Student| &s.last_name)
+//       .then_ref(|s: &Student| &s.first_name)
+//       .then(|s: &Student| s.age).desc();
+//   third::sort_by(&mut x, &order.comparator())?;
+//
+// keyとthenのキーは比較のたびに作られる。Stringのように複製に割り当てが必要なキーは、
+// 要素の中のキーへの参照を返すkey_refとthen_refを使うと、比較のたびに複製せずに済む
+//
+// 比較関数はSend + Syncなので、fourthのsort_byにもそのまま渡せる
+
+// 1つのキーの比較関数。Fieldsから作るOrderByと共有できるようにArcで持つ
+type Compare<T> = Arc<dyn Fn(&T, &T) -> Ordering + Send + Sync>;
+
+pub struct OrderBy<T> {
+    // 比較するキーとその順序。先頭のキーから順に比較する
+    keys: Vec<(Compare<T>, SortOrder)>,
+}
+
+impl<T> OrderBy<T> {
+    // keyが返すキーの昇順で比較する
+    pub fn key<K, F>(key: F) -> Self
+        where K: Ord,
+              F: Fn(&T) -> K + Send + Sync + 'static
+    {
+        Self { keys: Vec::new() }.then(key)
+    }
+
+    // keyが参照するキーの昇順で比較する
+    pub fn key_ref<K, F>(key: F) -> Self
+        where K: Ord + ?Sized,
+              F: for<'a> Fn(&'a T) -> &'a K + Send + Sync + 'static
+    {
+        Self { keys: Vec::new() }.then_ref(key)
+    }
+
+    // comparatorの順で比較する
+    pub fn by<F>(comparator: F) -> Self
+        where F: Fn(&T, &T) -> Ordering + Send + Sync + 'static
+    {
+        Self { keys: Vec::new() }.then_by(comparator)
+    }
+
+    // それまでのキーが等しいときに、keyが返すキーの昇順で比較する
+    pub fn then<K, F>(self, key: F) -> Self
+        where K: Ord,
+              F: Fn(&T) -> K + Send + Sync + 'static
+    {
+        self.then_by(move |a, b| key(a).cmp(&key(b)))
+    }
+
+    // それまでのキーが等しいときに、keyが参照するキーの昇順で比較する
+    pub fn then_ref<K, F>(self, key: F) -> Self
+        where K: Ord + ?Sized,
+              F: for<'a> Fn(&'a T) -> &'a K + Send + Sync + 'static
+    {
+        self.then_by(move |a, b| key(a).cmp(key(b)))
+    }
+
+    // それまでのキーが等しいときに、comparatorの順で比較する
+    pub fn then_by<F>(mut self, comparator: F) -> Self
+        where F: Fn(&T, &T) -> Ordering + Send + Sync + 'static
+    {
+        self.keys.push((Arc::new(comparator), SortOrder::Ascending));
+        self
+    }
+
+    // 最後に追加したキーを昇順で比較する
+    pub fn asc(self) -> Self {
+        self.last_key_order(SortOrder::Ascending)
+    }
+
+    // 最後に追加したキーを降順で比較する
+    pub fn desc(self) -> Self {
+        self.last_key_order(SortOrder::Descending)
+    }
+
+    // すべてのキーの順序を逆にする
+    pub fn reverse(mut self) -> Self {
+        for (_, order) in &mut self.keys {
+            *order = order.reverse();
+        }
+        self
+    }
+
+    fn last_key_order(mut self, order: SortOrder) -> Self {
+        if let Some((_, last)) = self.keys.last_mut() {
+            *last = order;
+        }
+        self
+    }
+
+    // 先頭のキーから順に比較し、最初に等しくなかった結果を返す
+    pub fn compare(&self, a: &T, b: &T) -> Ordering {
+        for (comparator, order) in &self.keys {
+            let ordering = match *order {
+                SortOrder::Ascending => comparator(a, b),
+                SortOrder::Descending => comparator(b, a),
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+
+    // 各モジュールのsort_byに渡せる比較関数を返す
+    pub fn comparator(&self) -> impl Fn(&T, &T) -> Ordering + Send + Sync + '_ {
+        move |a, b| self.compare(a, b)
+    }
+}
+
+// 名前で参照できるキーの一覧
+// 実行時に与えられる"last_name asc, age desc"のような指定から、OrderByを作るのに使う
+//
+//   let fields = Fields::new()
+//       .field_ref("last_name", |s: &Student| &s.last_name)
+//       .field("age", |s: &Student| s.age);
+//   let order = fields.parse("last_name asc, age desc")?;
+pub struct Fields<T> {
+    fields: Vec<(String, Compare<T>)>,
+}
+
+impl<T> Fields<T> {
+    pub fn new() -> Self {
+        Self { fields: Vec::new() }
+    }
+
+    // nameという名前で、keyが返すキーを登録する
+    pub fn field<K, F>(mut self, name: &str, key: F) -> Self
+        where K: Ord,
+              F: Fn(&T) -> K + Send + Sync + 'static
+    {
+        self.fields.push((name.to_string(), Arc::new(move |a, b| key(a).cmp(&key(b)))));
+        self
+    }
+
+    // nameという名前で、keyが参照するキーを登録する
+    pub fn field_ref<K, F>(mut self, name: &str, key: F) -> Self
+        where K: Ord + ?Sized,
+              F: for<'a> Fn(&'a T) -> &'a K + Send + Sync + 'static
+    {
+        self.fields.push((name.to_string(), Arc::new(move |a, b| key(a).cmp(key(b)))));
+        self
+    }
+
+    // "名前 [asc|desc]"をカンマで区切って並べた指定からOrderByを作る
+    // 順序を省略したキーは昇順で比較する。ascとdescは大文字でもよい
+    pub fn parse(&self, spec: &str) -> Result<OrderBy<T>, ParseOrderError> {
+        let mut keys = Vec::new();
+        for term in spec.split(',') {
+            let mut words = term.split_whitespace();
+            let name = words.next().ok_or(ParseOrderError::Empty)?;
+            let comparator = self.fields.iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, comparator)| Arc::clone(comparator))
+                .ok_or_else(|| ParseOrderError::UnknownField(name.to_string()))?;
+            let order = match words.next() {
+                None => SortOrder::Ascending,
+                Some(word) if word.eq_ignore_ascii_case("asc") => SortOrder::Ascending,
+                Some(word) if word.eq_ignore_ascii_case("desc") => SortOrder::Descending,
+                Some(word) => return Err(ParseOrderError::UnknownDirection(word.to_string())),
+            };
+            if let Some(word) = words.next() {
+                return Err(ParseOrderError::UnknownDirection(word.to_string()));
+            }
+            keys.push((comparator, order));
+        }
+        Ok(OrderBy { keys })
+    }
+}
+
+impl<T> Default for Fields<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Fields::parseに失敗したときのエラー
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseOrderError {
+    // キーの名前がない項目があった(空の指定や、連続したカンマ)
+    Empty,
+    // 登録されていない名前だった
+    UnknownField(String),
+    // 順序がascでもdescでもなかった
+    UnknownDirection(String),
+}
+
+impl fmt::Display for ParseOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseOrderError::Empty =>
+                write!(f, "The sort spec has an empty key."),
+            ParseOrderError::UnknownField(name) =>
+                write!(f, "The sort spec has an unknown field. (field: {})", name),
+            ParseOrderError::UnknownDirection(word) =>
+                write!(f, "The sort spec has an unknown direction. (direction: {})", word),
+        }
+    }
+}
+
+impl Error for ParseOrderError {}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{Fields, OrderBy, ParseOrderError};
+    use crate::{third, fourth};
+    use crate::SortOrder::*;
+    use crate::tests::{Student, students};
+    use std::cmp::Ordering;
+
+    fn first_names(x: &[Student]) -> Vec<&str> {
+        x.iter().map(|s| s.first_name.as_str()).collect()
+    }
+
+    fn fields() -> Fields<Student> {
+        Fields::new()
+            .field_ref("first_name", |s: &Student| &s.first_name)
+            .field("last_name", |s: &Student| s.last_name.clone())
+            .field("age", |s: &Student| s.age)
+    }
+
+    #[test]
+    fn sort_students_by_name() {
+        // third::testsのsort_students_by_name_ascendingと同じ順序を、then_withを書かずに作る
+        let order = OrderBy::key_ref(|s: &Student| &s.last_name)
+            .then_ref(|s: &Student| s.first_name.as_str());
+        let mut x = students();
+        assert_eq!(third::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(first_names(&x), vec!["Ryosuke", "Kyoko", "Hanako", "Taro"]);
+
+        let mut x = students();
+        assert_eq!(fourth::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(first_names(&x), vec!["Ryosuke", "Kyoko", "Hanako", "Taro"]);
+    }
+
+    #[test]
+    fn desc_applies_to_last_key() {
+        let order = OrderBy::key(|s: &Student| s.last_name.clone()).desc()
+            .then(|s: &Student| s.age);
+        let mut x = students();
+        assert_eq!(third::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(first_names(&x), vec!["Hanako", "Taro", "Kyoko", "Ryosuke"]);
+
+        // reverseはすべてのキーの順序を逆にする
+        let order = order.reverse();
+        assert_eq!(third::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(first_names(&x), vec!["Ryosuke", "Kyoko", "Taro", "Hanako"]);
+
+        let order = order.asc();
+        assert_eq!(order.compare(&x[2], &x[3]), Ordering::Greater);
+    }
+
+    #[test]
+    fn order_by_comparator() {
+        let order = OrderBy::by(|a: &u32, b: &u32| (a % 10).cmp(&(b % 10)))
+            .then(|&a: &u32| a).desc();
+        let mut x = vec![21, 10, 11, 30];
+        assert_eq!(third::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(x, vec![30, 10, 21, 11]);
+    }
+
+    #[test]
+    fn parse_spec() {
+        let order = fields().parse("last_name asc, age desc").unwrap();
+        let mut x = students();
+        assert_eq!(fourth::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(first_names(&x), vec!["Ryosuke", "Kyoko", "Taro", "Hanako"]);
+
+        // 順序を省略すると昇順になる。大文字も使える
+        let order = fields().parse(" last_name DESC ,first_name").unwrap();
+        assert_eq!(third::sort_by(&mut x, &order.comparator()), Ok(()));
+        assert_eq!(first_names(&x), vec!["Hanako", "Taro", "Kyoko", "Ryosuke"]);
+    }
+
+    #[test]
+    fn parse_spec_errors() {
+        assert_eq!(fields().parse("").err(), Some(ParseOrderError::Empty));
+        assert_eq!(fields().parse("age,,last_name").err(), Some(ParseOrderError::Empty));
+        assert_eq!(fields().parse("grade desc").err(),
+                   Some(ParseOrderError::UnknownField("grade".to_string())));
+        assert_eq!(fields().parse("age down").err(),
+                   Some(ParseOrderError::UnknownDirection("down".to_string())));
+        assert_eq!(fields().parse("age asc desc").err(),
+                   Some(ParseOrderError::UnknownDirection("desc".to_string())));
+        assert_eq!(ParseOrderError::UnknownField("grade".to_string()).to_string(),
+                   "The sort spec has an unknown field. (field: grade)");
+    }
+
+    #[test]
+    fn reverse_sort_order() {
+        assert_eq!(Ascending.reverse(), Descending);
+        assert_eq!(Descending.reverse(), Ascending);
+    }
+}
@@ -0,0 +1,553 @@
+use super::{SortOrder, no_values};
+use super::{second, third, odd_even};
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::mem;
+use std::sync::Mutex;
+
+// ソーティングネットワークの比較器をすべて書き出す
+// do_sort・sub_sort・compare_and_swapが比較する要素の組は入力によらないので、
+// 要素数nだけから、実行される比較器の列を作れる
+// 比較器は、各モジュールのdo_sortを記録用の要素の列に対して実際に実行して記録する
+// そのため、ソートの実装を変えると、記録されるネットワークも同じように変わる
+//
+// 比較器は互いに独立な比較器の層(段)にまとめてから、JSON、SVG、
+// Knuthの図式のテキストとして出力する。ハードウェアやGPUのカーネルを生成したり、
+// 設計レビューでネットワークの形を確かめたりするのに使う
+
+// 1つの比較器。要素iとjを比較し(i < j)、directionの順に並べる
+// Ascendingならiに小さい方、Descendingならiに大きい方を置く
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparator {
+    pub i: usize,
+    pub j: usize,
+    pub direction: SortOrder,
+}
+
+// 要素数lenのソーティングネットワーク
+// layersの各層の比較器は、どの2つも同じ要素を共有しないので、同時に実行できる
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    len: usize,
+    layers: Vec<Vec<Comparator>>,
+}
+
+impl Network {
+    // secondが実行するバイトニックソートのネットワーク
+    // 要素数は2のべき乗でなくてもよい(sort_arbitraryと同じネットワークになる)
+    pub fn bitonic(len: usize) -> Self {
+        record(len, |x| second::do_sort(x, true))
+    }
+
+    // third、fourth、iterativeが実行するネットワーク
+    // バイトニックソートの再帰の末端を、16要素以下の固定長のネットワークに置き換えたもの
+    pub fn bitonic_with_small_networks(len: usize) -> Self {
+        record(len, |x| third::do_sort(x, &mut no_values(len), true, &|a: &Wire, b: &Wire| a.cmp(b)))
+    }
+
+    // odd_evenが実行する、Batcherの奇偶マージソートのネットワーク
+    pub fn odd_even(len: usize) -> Self {
+        record(len, |x| odd_even::do_sort(x, &|a: &Wire, b: &Wire| a.cmp(b)))
+    }
+
+    // 実行順に並んだ比較器を、できるだけ前の層に詰めて層に分ける
+    // 同じ要素を使う比較器の順序は変わらないので、ネットワークの動作は変わらない
+    fn from_comparators(len: usize, comparators: Vec<Comparator>) -> Self {
+        // depth[k]は、要素kを使う比較器を置ける最初の層
+        let mut depth = vec![0; len];
+        let mut layers: Vec<Vec<Comparator>> = Vec::new();
+        for c in comparators {
+            let layer = depth[c.i].max(depth[c.j]);
+            if layer == layers.len() {
+                layers.push(Vec::new());
+            }
+            layers[layer].push(c);
+            depth[c.i] = layer + 1;
+            depth[c.j] = layer + 1;
+        }
+        Self { len, layers }
+    }
+
+    // ネットワークの要素数(ワイヤの本数)
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn layers(&self) -> &[Vec<Comparator>] {
+        &self.layers
+    }
+
+    // 比較器の総数
+    pub fn size(&self) -> usize {
+        self.layers.iter().map(|layer| layer.len()).sum()
+    }
+
+    // 層の数。すべての層を並列に実行したときの段数になる
+    pub fn depth(&self) -> usize {
+        self.layers.len()
+    }
+
+    // 実行順に比較器を返すイテレータ
+    pub fn comparators(&self) -> impl Iterator<Item = &Comparator> {
+        self.layers.iter().flatten()
+    }
+
+    // JSONとして出力する。比較器は[i, j, "asc"|"desc"]の配列で表し、1行に1層を書く
+    //
+    //   {"len": 2, "size": 1, "depth": 1, "layers": [
+    //     [[0, 1, "asc"]]
+    //   ]}
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        writeln!(json, r#"{{"len": {}, "size": {}, "depth": {}, "layers": ["#,
+                 self.len, self.size(), self.depth()).unwrap();
+        for (n, layer) in self.layers.iter().enumerate() {
+            let comparators: Vec<_> = layer.iter()
+                .map(|c| format!(r#"[{}, {}, "{}"]"#, c.i, c.j, direction_name(&c.direction)))
+                .collect();
+            let separator = if n + 1 < self.layers.len() { "," } else { "" };
+            writeln!(json, "  [{}]{}", comparators.join(", "), separator).unwrap();
+        }
+        json.push_str("]}\n");
+        json
+    }
+
+    // Knuthの図式をSVGとして出力する
+    // ワイヤを横線、比較器を縦線で描き、大きい方の要素が移る端を矢印の向きで示す
+    pub fn to_svg(&self) -> String {
+        const MARGIN: usize = 20;
+        const WIRE_GAP: usize = 20;
+        const COLUMN_GAP: usize = 12;
+        const LAYER_GAP: usize = 24;
+
+        // 比較器ごとにx座標を決める。層の中で縦線が重なるものは、別の列にずらす
+        let mut lines = Vec::new();
+        let mut x = MARGIN;
+        for layer in &self.layers {
+            let columns = columns(layer);
+            let width = columns.iter().max().map_or(0, |&c| c);
+            for (c, column) in layer.iter().zip(&columns) {
+                lines.push((x + column * COLUMN_GAP, c));
+            }
+            x += width * COLUMN_GAP + LAYER_GAP;
+        }
+        // 比較器がないときも、ワイヤを描く幅は確保する
+        let width = x.saturating_sub(LAYER_GAP).max(MARGIN) + MARGIN;
+        let height = MARGIN * 2 + self.len.saturating_sub(1) * WIRE_GAP;
+        let y = |wire: usize| MARGIN + wire * WIRE_GAP;
+
+        let mut svg = String::new();
+        writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+                 width, height, width, height).unwrap();
+        writeln!(svg, r#"<g stroke="black" stroke-width="1.5">"#).unwrap();
+        for wire in 0..self.len {
+            writeln!(svg, r#"<line x1="{}" y1="{}" x2="{}" y2="{}"/>"#,
+                     MARGIN / 2, y(wire), width - MARGIN / 2, y(wire)).unwrap();
+        }
+        for (x, c) in lines {
+            // 大きい方の要素が移る端に矢じりを描く
+            let (min, max) = match c.direction {
+                SortOrder::Ascending => (c.i, c.j),
+                SortOrder::Descending => (c.j, c.i),
+            };
+            let head = if max > min { y(max) - 6 } else { y(max) + 6 };
+            writeln!(svg, r#"<line x1="{}" y1="{}" x2="{}" y2="{}"/>"#, x, y(c.i), x, y(c.j)).unwrap();
+            writeln!(svg, r#"<circle cx="{}" cy="{}" r="3"/>"#, x, y(min)).unwrap();
+            writeln!(svg, r#"<polygon points="{},{} {},{} {},{}"/>"#,
+                     x, y(max), x - 4, head, x + 4, head).unwrap();
+        }
+        svg.push_str("</g>\n</svg>\n");
+        svg
+    }
+
+    // Knuthの図式をテキストとして出力する
+    // 比較器の端は、小さい方の要素が移る側を'o'、大きい方が移る側を'v'か'^'で表す
+    //
+    // 4要素のバイトニックソートなら、次のようになる
+    //
+    //   0 -^--o----o--
+    //      |  |    |
+    //   1 -o--|-o--v--
+    //         | |
+    //   2 -o--v-|--o--
+    //      |    |  |
+    //   3 -v----v--v--
+    pub fn to_text(&self) -> String {
+        // rows[2 * k]はワイヤkの行、rows[2 * k + 1]はワイヤkとk + 1の間の行
+        let rows_len = (2 * self.len).saturating_sub(1);
+        let mut rows: Vec<Vec<char>> = vec![Vec::new(); rows_len];
+        // 図の1列分の文字を、各行の末尾に追加する
+        fn push(rows: &mut [Vec<char>], column: &[char]) {
+            for (row, &ch) in rows.iter_mut().zip(column) {
+                row.push(ch);
+            }
+        }
+        let blank: Vec<char> = (0..rows_len).map(|r| if r % 2 == 0 { '-' } else { ' ' }).collect();
+
+        push(&mut rows, &blank);
+        for layer in &self.layers {
+            let columns = columns(layer);
+            let width = columns.iter().max().map_or(0, |&c| c + 1);
+            for column in 0..width {
+                let mut chars = blank.clone();
+                for (c, _) in layer.iter().zip(&columns).filter(|&(_, &col)| col == column) {
+                    for ch in &mut chars[2 * c.i + 1..2 * c.j] {
+                        *ch = '|';
+                    }
+                    let (min_char, max_char) = match c.direction {
+                        SortOrder::Ascending => ('o', 'v'),
+                        SortOrder::Descending => ('^', 'o'),
+                    };
+                    chars[2 * c.i] = min_char;
+                    chars[2 * c.j] = max_char;
+                }
+                push(&mut rows, &chars);
+                push(&mut rows, &blank);
+            }
+            push(&mut rows, &blank);
+        }
+
+        // 行頭にワイヤの番号を、桁をそろえて付ける
+        let label_width = self.len.saturating_sub(1).to_string().len();
+        let mut text = String::new();
+        for (r, row) in rows.iter().enumerate() {
+            let label = if r % 2 == 0 { (r / 2).to_string() } else { String::new() };
+            let row: String = row.iter().collect();
+            writeln!(text, "{:>width$} {}", label, row.trim_end(), width = label_width).unwrap();
+        }
+        text
+    }
+}
+
+fn direction_name(direction: &SortOrder) -> &'static str {
+    match *direction {
+        SortOrder::Ascending => "asc",
+        SortOrder::Descending => "desc",
+    }
+}
+
+// 層の中の比較器を図の列に割り当てる。縦線の範囲が重なる比較器は別の列に置く
+fn columns(layer: &[Comparator]) -> Vec<usize> {
+    // ends[col]は、列colに置いた縦線の下端
+    let mut ends: Vec<usize> = Vec::new();
+    layer.iter()
+        .map(|c| {
+            match ends.iter().position(|&end| end < c.i) {
+                Some(col) => {
+                    ends[col] = c.j;
+                    col
+                }
+                None => {
+                    ends.push(c.j);
+                    ends.len() - 1
+                }
+            }
+        })
+        .collect()
+}
+
+// sortで要素数lenの列をソートし、実行された比較器を記録する
+// sortは記録用の要素Wireの列を、いずれかのモジュールのdo_sortでソートする
+fn record<S>(len: usize, sort: S) -> Network
+    where S: FnOnce(&mut [Wire])
+{
+    let probe = Probe { state: Mutex::new(ProbeState::new(len)) };
+    let mut x: Vec<Wire> = (0..len).map(|id| Wire { id, probe: &probe }).collect();
+    probe.state.lock().unwrap().base = x.as_ptr() as usize;
+    sort(&mut x);
+
+    let mut state = probe.state.lock().unwrap();
+    // 最後に比較された後の交換の有無は、ソート後の並びから分かる
+    for (position, wire) in x.iter().enumerate() {
+        state.resolve(position, wire.id);
+    }
+    let comparators = state.calls.iter()
+        .map(|call| {
+            let swapped = call.swapped.expect("every comparison is resolved");
+            // 比較関数にはGreaterを返したので、交換したなら小さい方を1つ目の引数の位置pに置いた
+            let ascending = swapped == (call.p < call.q);
+            comparator(call.p.min(call.q), call.p.max(call.q), ascending)
+        })
+        .collect();
+    Network::from_comparators(len, comparators)
+}
+
+// 記録に使う要素。idは要素を区別する番号
+// 比較されると、比較した2要素の位置をprobeに記録する
+struct Wire<'a> {
+    id: usize,
+    probe: &'a Probe,
+}
+
+impl Ord for Wire<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.probe.compare(self, other)
+    }
+}
+
+impl PartialOrd for Wire<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Wire<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Wire<'_> {}
+
+// 比較を記録する。fourthのdo_sortも記録できるように、Mutexで包んでスレッド間で共有する
+struct Probe {
+    state: Mutex<ProbeState>,
+}
+
+// 1回の比較。位置pとqの要素を、この順に比較関数へ渡した
+struct Call {
+    p: usize,
+    q: usize,
+    // 比較したときにpとqにあった要素のid
+    id_p: usize,
+    id_q: usize,
+    // 比較の後に2要素を交換したか。次にpかqの要素が比較されたときに分かる
+    swapped: Option<bool>,
+}
+
+struct ProbeState {
+    // 要素の列の先頭のアドレス。比較関数に渡された参照から位置を求めるのに使う
+    base: usize,
+    calls: Vec<Call>,
+    // pending[k]は、位置kを最後に比較した、交換の有無がまだ分からない比較
+    pending: Vec<Option<usize>>,
+}
+
+impl ProbeState {
+    fn new(len: usize) -> Self {
+        Self { base: 0, calls: Vec::new(), pending: vec![None; len] }
+    }
+
+    fn position(&self, wire: &Wire) -> usize {
+        (wire as *const Wire as usize - self.base) / mem::size_of::<Wire>()
+    }
+
+    // 位置positionに要素idがあることが分かったときに、
+    // その位置を最後に比較したときの交換の有無を決める
+    fn resolve(&mut self, position: usize, id: usize) {
+        if let Some(k) = self.pending[position].take() {
+            let call = &mut self.calls[k];
+            let id_before = if position == call.p { call.id_p } else { call.id_q };
+            call.swapped = Some(id != id_before);
+            self.pending[call.p] = None;
+            self.pending[call.q] = None;
+        }
+    }
+}
+
+impl Probe {
+    // aとbの位置と、それぞれを前回比較した後に交換されたかを記録する
+    // 常にGreaterを返す。ソートはforwardの向きのときだけ交換するので、
+    // 次にその位置が比較されたときに、比較器の向きが分かる
+    fn compare(&self, a: &Wire, b: &Wire) -> Ordering {
+        let mut state = self.state.lock().unwrap();
+        let (p, q) = (state.position(a), state.position(b));
+        state.resolve(p, a.id);
+        state.resolve(q, b.id);
+        let k = state.calls.len();
+        state.calls.push(Call { p, q, id_p: a.id, id_q: b.id, swapped: None });
+        state.pending[p] = Some(k);
+        state.pending[q] = Some(k);
+        Ordering::Greater
+    }
+}
+
+fn comparator(i: usize, j: usize, ascending: bool) -> Comparator {
+    let direction = if ascending { SortOrder::Ascending } else { SortOrder::Descending };
+    Comparator { i, j, direction }
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{record, Comparator, Network, Wire};
+    use crate::SortOrder::*;
+    use crate::{iterative, third, fourth, odd_even, no_values};
+    use crate::fourth::SortConfig;
+    use crate::utils::new_u32_vec;
+    use std::cell::Cell;
+    use std::cmp::Ordering;
+
+    // ネットワークを実行して、xをソートする
+    fn apply(network: &Network, x: &mut [u32]) {
+        for c in network.comparators() {
+            let swap = match c.direction {
+                Ascending => x[c.i] > x[c.j],
+                Descending => x[c.i] < x[c.j],
+            };
+            if swap {
+                x.swap(c.i, c.j);
+            }
+        }
+    }
+
+    // 0-1原理で、記録したネットワークがソーティングネットワークであることを確かめる
+    #[test]
+    fn recorded_networks_sort_all_zero_one_inputs() {
+        for n in 0..=13 {
+            for network in &[Network::bitonic(n), Network::bitonic_with_small_networks(n),
+                             Network::odd_even(n)] {
+                assert_eq!(network.len(), n);
+                for bits in 0u32..(1 << n) {
+                    let mut x: Vec<u32> = (0..n).map(|i| (bits >> i) & 1).collect();
+                    apply(network, &mut x);
+                    assert!(x.windows(2).all(|w| w[0] <= w[1]), "n = {}, input = {:b}", n, bits);
+                }
+            }
+        }
+    }
+
+    // 同じ層の比較器は、同じ要素を共有しない
+    #[test]
+    fn layers_are_independent() {
+        let network = Network::bitonic_with_small_networks(100);
+        for layer in network.layers() {
+            let mut used = vec![false; network.len()];
+            for c in layer {
+                assert!(c.i < c.j);
+                assert!(!used[c.i] && !used[c.j]);
+                used[c.i] = true;
+                used[c.j] = true;
+            }
+        }
+    }
+
+    // sortでn要素をソートしたときの比較の回数を数える
+    fn count_comparisons<S>(n: usize, sort: S) -> usize
+        where S: Fn(&mut [u32], &dyn Fn(&u32, &u32) -> Ordering)
+    {
+        let count = Cell::new(0usize);
+        let mut x = new_u32_vec(n);
+        sort(&mut x, &|a, b| { count.set(count.get() + 1); a.cmp(b) });
+        count.get()
+    }
+
+    // 記録した比較器の数が、ソートが実際に比較する回数と一致する
+    // check-sortedフィーチャーではソート後の検証の比較も数えてしまうので実行しない
+    #[test]
+    #[cfg_attr(feature = "check-sorted", ignore)]
+    fn size_matches_comparisons() {
+        for &n in &[1, 16, 64, 1024] {
+            assert_eq!(Network::bitonic_with_small_networks(n).size(),
+                       count_comparisons(n, |x, f| iterative::sort_by(x, &f).unwrap()));
+        }
+        for &n in &[1, 16, 17, 100, 1024] {
+            assert_eq!(Network::bitonic_with_small_networks(n).size(),
+                       count_comparisons(n, |x, f| third::sort_by_arbitrary(x, &f).unwrap()));
+            assert_eq!(Network::odd_even(n).size(),
+                       count_comparisons(n, |x, f| odd_even::sort_by_arbitrary(x, &f).unwrap()));
+        }
+        assert_eq!(Network::bitonic(1024).size(), 28_160);
+        assert_eq!(Network::bitonic(1024).depth(), 55);
+    }
+
+    // 層ごとに比較器を並べ替えて比べる
+    // 同じネットワークでも、同じ層の比較器を実行する順序はソートによって異なる
+    fn sorted_layers(network: &Network) -> Vec<Vec<(usize, usize, bool)>> {
+        network.layers().iter()
+            .map(|layer| {
+                let mut layer: Vec<_> = layer.iter().map(|c| (c.i, c.j, c.direction == Ascending)).collect();
+                layer.sort();
+                layer
+            })
+            .collect()
+    }
+
+    // iterativeとfourthも、thirdと同じネットワークを実行する
+    #[test]
+    fn sorters_run_the_same_network() {
+        for &n in &[1, 2, 16, 32, 64, 256] {
+            let expected = sorted_layers(&Network::bitonic_with_small_networks(n));
+            let network = record(n, |x| iterative::do_sort(x, true, &|a: &Wire, b: &Wire| a.cmp(b)));
+            assert_eq!(sorted_layers(&network), expected);
+        }
+        for n in (0..40).chain(vec![100, 257]) {
+            let expected = sorted_layers(&Network::bitonic_with_small_networks(n));
+            let config = SortConfig::new().threshold(8);
+            let network = record(n, |x| {
+                fourth::do_sort(x, &mut no_values(n), true, &|a: &Wire, b: &Wire| a.cmp(b), &config, 0, 0)
+            });
+            assert_eq!(sorted_layers(&network), expected);
+        }
+    }
+
+    // 記録したネットワークを実行すると、ソートと同じ結果になる
+    // キーが等しい要素の並び順まで一致するので、比較器の向きも一致していることが分かる
+    #[test]
+    fn network_reproduces_sort() {
+        for &n in &[5, 16, 100, 1024] {
+            let original: Vec<(u32, usize)> = new_u32_vec(n).iter().map(|v| v % 4).zip(0..).collect();
+            let mut expected = original.clone();
+            assert_eq!(third::sort_by_arbitrary(&mut expected, &|a, b| a.0.cmp(&b.0)), Ok(()));
+
+            let mut x = original.clone();
+            for c in Network::bitonic_with_small_networks(n).comparators() {
+                let swap = match c.direction {
+                    Ascending => x[c.i].0 > x[c.j].0,
+                    Descending => x[c.i].0 < x[c.j].0,
+                };
+                if swap {
+                    x.swap(c.i, c.j);
+                }
+            }
+            assert_eq!(x, expected);
+        }
+    }
+
+    #[test]
+    fn export_json() {
+        assert_eq!(Network::bitonic(3).to_json(), concat!(
+            r#"{"len": 3, "size": 3, "depth": 3, "layers": ["#, "\n",
+            r#"  [[1, 2, "asc"]],"#, "\n",
+            r#"  [[0, 2, "asc"]],"#, "\n",
+            r#"  [[0, 1, "asc"]]"#, "\n",
+            "]}\n"));
+    }
+
+    #[test]
+    fn export_text() {
+        assert_eq!(Network::bitonic(4).to_text(), concat!(
+            "0 -^--o----o--\n",
+            "   |  |    |\n",
+            "1 -o--|-o--v--\n",
+            "      | |\n",
+            "2 -o--v-|--o--\n",
+            "   |    |  |\n",
+            "3 -v----v--v--\n"));
+    }
+
+    #[test]
+    fn export_svg() {
+        let svg = Network::odd_even(8).to_svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<polygon").count(), 19);
+        assert_eq!(svg.matches("<circle").count(), 19);
+
+        let svg = Network::bitonic(1).to_svg();
+        assert_eq!(svg.matches("<line").count(), 1);
+    }
+
+    #[test]
+    fn empty_network() {
+        let network = Network::bitonic(0);
+        assert!(network.is_empty());
+        assert_eq!(network.size(), 0);
+        assert_eq!(network.comparators().next(), None::<&Comparator>);
+        assert_eq!(network.to_text(), "");
+    }
+}
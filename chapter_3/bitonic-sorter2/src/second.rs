@@ -1,9 +1,9 @@
-use super::SortOrder;
+use super::{SortOrder, SortError, greatest_power_of_two_less_than};
 
 // u32型は32ビット符号なし整数
 // [u32]型はu32のスライス(1次元配列のようなもの)
 
-pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
     if x.len().is_power_of_two() {
         match *order {
             SortOrder::Ascending => do_sort(x, true),
@@ -11,16 +11,28 @@ pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
         };
         Ok(())
     } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
+        Err(SortError::NotPowerOfTwo(x.len()))
     }
 }
 
-fn do_sort<T: Ord>(x: &mut [T], up: bool) {
+// 要素数が2のべき乗でなくてもソートできるモード
+// 仮想的なパディングを使うバイトニックネットワークなので、余分なメモリを確保しない
+pub fn sort_arbitrary<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => do_sort(x, true),
+        SortOrder::Descending => do_sort(x, false),
+    };
+    Ok(())
+}
+
+pub(crate) fn do_sort<T: Ord>(x: &mut [T], up: bool) {
     // 未実装の意味。コンパイルは徹が、実行するとpanicする
     if x.len() > 1 {
         let mid_point = x.len() / 2;
-        do_sort(&mut x[..mid_point], true);
-        do_sort(&mut x[mid_point..], false);
+        // 前半をupとは逆順に、後半をupの順にソートする
+        // こうしておくと、要素数が2のべき乗でなくてもsub_sortでマージできる
+        do_sort(&mut x[..mid_point], !up);
+        do_sort(&mut x[mid_point..], up);
         sub_sort(x, up);
     }
 }
@@ -28,15 +40,18 @@ fn do_sort<T: Ord>(x: &mut [T], up: bool) {
 fn sub_sort<T: Ord>(x: &mut [T], up: bool) {
     if x.len() > 1 {
         compare_and_swap(x, up);
-        let mid_point = x.len() / 2;
+        // 要素数が2のべき乗ならx.len() / 2と同じ位置で分割する
+        let mid_point = greatest_power_of_two_less_than(x.len());
         sub_sort(&mut x[..mid_point], up);
         sub_sort(&mut x[mid_point..], up);
     }
 }
 
 fn compare_and_swap<T: Ord>(x: &mut [T], up: bool) {
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+    // 要素数が2のべき乗でないときは、末尾に仮想的な要素があるものとして
+    // 相手が存在する要素だけを比較する
+    let mid_point = greatest_power_of_two_less_than(x.len());
+    for i in 0..(x.len() - mid_point) {
         if (x[i] > x[mid_point + i]) == up {
             x.swap(i, mid_point + i);
         }
@@ -48,8 +63,10 @@ fn compare_and_swap<T: Ord>(x: &mut [T], up: bool) {
 #[cfg(test)]
 mod tests {
     // 親モジュール(second)のsort関数を使用する
-    use super::sort;
+    use super::{sort, sort_arbitrary};
+    use crate::SortError;
     use crate::SortOrder::*;
+    use crate::utils::new_u32_vec;
 
     // #[test]のついた関数はcargo testとしたときに実行される
     #[test]
@@ -111,4 +128,29 @@ mod tests {
         let mut x = vec![10, 30, 11];
         assert!(sort(&mut x, &Ascending).is_err());
     }
+
+    #[test]
+    fn sort_error_has_length() {
+        let mut x = vec![10, 30, 11];
+        // エラーのバリアントでマッチでき、問題になった要素数を取り出せる
+        let err = sort(&mut x, &Ascending).unwrap_err();
+        assert_eq!(err, SortError::NotPowerOfTwo(3));
+        assert_eq!(err.to_string(), "The length of x is not a power of two. (x.len(): 3)");
+    }
+
+    #[test]
+    fn sort_arbitrary_length() {
+        // 0から100要素まで、すべての要素数でソートできることを確認する
+        for n in 0..=100 {
+            let mut x = new_u32_vec(n);
+            let mut expected = x.clone();
+            expected.sort();
+            assert_eq!(sort_arbitrary(&mut x, &Ascending), Ok(()));
+            assert_eq!(x, expected);
+
+            expected.reverse();
+            assert_eq!(sort_arbitrary(&mut x, &Descending), Ok(()));
+            assert_eq!(x, expected);
+        }
+    }
 }
@@ -0,0 +1,192 @@
+use super::{SortOrder, SortError, greatest_power_of_two_less_than};
+
+// 比較交換を分岐なしで行い、SIMD命令に置き換えられる要素の型
+// u32, i32, u64, f32に実装する。それ以外の型には実装できないように封印(sealed)してある
+//
+// このモジュールのsortとsort_arbitraryは、SimdKeyの要素の列をソートする専用の入口で、
+// thirdやfourthのsortからは呼ばれない。プリミティブ型の大きな列をソートするときに明示的に使う
+// f32はOrdを実装しないのでthirdやfourthのsortには渡せないが、このモジュールのsortならソートできる
+pub trait SimdKey: private::Sealed + Copy {
+    // aがbより小さければtrueを返す。分岐を作らない比較にする
+    fn less(a: Self, b: Self) -> bool;
+}
+
+mod private {
+    // クレートの外からSimdKeyを実装させないためのトレイト
+    pub trait Sealed {}
+
+    impl Sealed for u32 {}
+    impl Sealed for i32 {}
+    impl Sealed for u64 {}
+    impl Sealed for f32 {}
+}
+
+impl SimdKey for u32 {
+    fn less(a: Self, b: Self) -> bool {
+        a < b
+    }
+}
+
+impl SimdKey for i32 {
+    fn less(a: Self, b: Self) -> bool {
+        a < b
+    }
+}
+
+impl SimdKey for u64 {
+    fn less(a: Self, b: Self) -> bool {
+        a < b
+    }
+}
+
+// f32はf32::total_cmpと同じ全順序で比較する
+// -0.0 < +0.0となり、NaNも符号に応じて先頭か末尾にまとまる
+impl SimdKey for f32 {
+    fn less(a: Self, b: Self) -> bool {
+        total_order_key(a) < total_order_key(b)
+    }
+}
+
+// 符号付き整数として比較するとtotal_cmpと同じ順序になるように、f32のビット列を変換する
+// 負の数は符号以外のビットを反転させる(f32::total_cmpと同じ方法)
+fn total_order_key(x: f32) -> i32 {
+    let bits = x.to_bits() as i32;
+    bits ^ (((bits >> 31) as u32) >> 1) as i32
+}
+
+pub fn sort<T: SimdKey>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    if x.len().is_power_of_two() {
+        sort_arbitrary(x, order)
+    } else {
+        Err(SortError::NotPowerOfTwo(x.len()))
+    }
+}
+
+// 要素数が2のべき乗でなくてもソートできるモード
+pub fn sort_arbitrary<T: SimdKey>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => do_sort(x, true),
+        SortOrder::Descending => do_sort(x, false),
+    };
+    Ok(())
+}
+
+fn do_sort<T: SimdKey>(x: &mut [T], forward: bool) {
+    if x.len() > 1 {
+        let mid_point = x.len() / 2;
+        do_sort(&mut x[..mid_point], !forward);
+        do_sort(&mut x[mid_point..], forward);
+        sub_sort(x, forward);
+    }
+}
+
+fn sub_sort<T: SimdKey>(x: &mut [T], forward: bool) {
+    if x.len() > 1 {
+        half_clean(x, forward);
+        let mid_point = greatest_power_of_two_less_than(x.len());
+        sub_sort(&mut x[..mid_point], forward);
+        sub_sort(&mut x[mid_point..], forward);
+    }
+}
+
+// バイトニックマージの1段分の比較交換(ハーフクリーナー)
+fn half_clean<T: SimdKey>(x: &mut [T], forward: bool) {
+    let mid_point = greatest_power_of_two_less_than(x.len());
+    let (first, second) = x.split_at_mut(mid_point);
+    // 比較する相手が存在する範囲に揃える
+    let first = &mut first[..second.len()];
+
+    // 小さい方を置く側をlo、大きい方を置く側をhiとする
+    let (lo, hi) = if forward { (first, second) } else { (second, first) };
+    min_max(lo, hi);
+}
+
+// lo[i]とhi[i]を比べ、loに小さい方、hiに大きい方を置く
+fn min_max<T: SimdKey>(lo: &mut [T], hi: &mut [T]) {
+    for (lo, hi) in lo.iter_mut().zip(hi.iter_mut()) {
+        // 比較結果で値の組を選ぶだけにして分岐を作らないので、
+        // コンパイラがループをSIMDの比較・選択命令に置き換える
+        // 交換するときだけ書き込む形にすると、要素ごとの条件付きストアになりSIMD化されない
+        let (a, b) = (*lo, *hi);
+        let (min, max) = if T::less(b, a) { (b, a) } else { (a, b) };
+        *lo = min;
+        *hi = max;
+    }
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{sort, sort_arbitrary};
+    use crate::SortError;
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+
+    #[test]
+    fn sort_u32_large() {
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert!(is_sorted_ascending(&x));
+
+        let mut x = new_u32_vec(65536);
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        assert!(is_sorted_descending(&x));
+
+        let mut x = new_u32_vec(3);
+        assert_eq!(sort(&mut x, &Ascending), Err(SortError::NotPowerOfTwo(3)));
+    }
+
+    #[test]
+    fn sort_primitives_arbitrary_length() {
+        // slice::sortと結果が一致することを確認する
+        for n in (0..100).chain(vec![1000, 4097]) {
+            let x = new_u32_vec(n);
+
+            let mut y = x.clone();
+            let mut expected = x.clone();
+            expected.sort();
+            assert_eq!(sort_arbitrary(&mut y, &Ascending), Ok(()));
+            assert_eq!(y, expected);
+
+            let mut y: Vec<i32> = x.iter().map(|&v| v as i32).collect();
+            let mut expected = y.clone();
+            expected.sort_by(|a, b| b.cmp(a));
+            assert_eq!(sort_arbitrary(&mut y, &Descending), Ok(()));
+            assert_eq!(y, expected);
+
+            let mut y: Vec<u64> = x.iter().map(|&v| (v as u64) << 20 | v as u64).collect();
+            let mut expected = y.clone();
+            expected.sort();
+            assert_eq!(sort_arbitrary(&mut y, &Ascending), Ok(()));
+            assert_eq!(y, expected);
+
+            let mut y: Vec<f32> = x.iter().map(|&v| v as f32 - 2e9).collect();
+            let mut expected = y.clone();
+            expected.sort_by(|a, b| b.partial_cmp(a).unwrap());
+            assert_eq!(sort_arbitrary(&mut y, &Descending), Ok(()));
+            assert_eq!(y, expected);
+        }
+    }
+
+    #[test]
+    fn sort_f32_in_total_order() {
+        // f32::total_cmpと同じ順序になる。正のNaNは末尾に、負のNaNは先頭に置かれる
+        let mut x = vec![3.0, f32::NAN, 1.0, 0.0, 2.0, -f32::NAN, -0.0, -1.0];
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        let bits: Vec<u32> = x.iter().map(|v| v.to_bits()).collect();
+        let expected = [-f32::NAN, -1.0, -0.0, 0.0, 1.0, 2.0, 3.0, f32::NAN];
+        assert_eq!(bits, expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+
+        assert_eq!(sort(&mut x, &Descending), Ok(()));
+        let bits: Vec<u32> = x.iter().map(|v| v.to_bits()).collect();
+        assert_eq!(bits, expected.iter().rev().map(|v| v.to_bits()).collect::<Vec<_>>());
+
+        // 大きな列でもf32::total_cmpと一致する
+        let mut x: Vec<f32> = new_u32_vec(4096).iter().map(|&v| f32::from_bits(v)).collect();
+        let mut expected = x.clone();
+        expected.sort_by(|a, b| a.total_cmp(b));
+        assert_eq!(sort(&mut x, &Ascending), Ok(()));
+        assert_eq!(x.iter().map(|v| v.to_bits()).collect::<Vec<_>>(),
+                   expected.iter().map(|v| v.to_bits()).collect::<Vec<_>>());
+    }
+}
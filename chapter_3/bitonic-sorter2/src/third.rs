@@ -1,44 +1,59 @@
-use super::SortOrder;
+use super::{SortOrder, SortError, apply_permutation, greatest_power_of_two_less_than, no_values};
+use super::networks;
+use super::utils::check_sorted_by;
 use std::cmp::Ordering;
 
-pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), String> {
+pub fn sort<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
     // do_sortを呼ぶ代わりに、sort_byを呼ぶようにする
-    // is_power_of_twoはsort_byが呼ぶだめ、ここからは削除
-    if x.len().is_power_of_two() {
-        match *order {
-            SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
-            SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
-        };
-        Ok(())
-    } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
+    // is_power_of_twoはsort_byが呼ぶため、ここからは削除
+    match *order {
+        SortOrder::Ascending => sort_by(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by(x, &|a, b| b.cmp(a)),
     }
 }
 
-fn do_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+// 要素数が2のべき乗でなくてもソートできるモード
+pub fn sort_arbitrary<T: Ord>(x: &mut [T], order: &SortOrder) -> Result<(), SortError> {
+    match *order {
+        SortOrder::Ascending => sort_by_arbitrary(x, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_by_arbitrary(x, &|a, b| b.cmp(a)),
+    }
+}
+
+// xをソートする。xの要素を入れ替えるときは、valuesの同じ位置の要素も一緒に入れ替える
+// キーだけをソートするときは、valuesにno_valuesで作った()の列を渡す
+pub(crate) fn do_sort<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F)
     where F: Fn(&T, &T) -> Ordering
 {    
-    if x.len() > 1 {
+    if x.len() <= networks::MAX_LEN {
+        // 小さな部分列は、比較器の少ない固定長のネットワークでソートする
+        networks::sort_small(x, values, forward, comparator);
+    } else {
         let mid_point = x.len() / 2;
-        do_sort(&mut x[..mid_point], true, comparator);
-        do_sort(&mut x[mid_point..], false, comparator);
+        let (first_values, second_values) = values.split_at_mut(mid_point);
+        // 前半をforwardとは逆順に、後半をforwardの順にソートする
+        // こうしておくと、要素数が2のべき乗でなくてもsub_sortでマージできる
+        do_sort(&mut x[..mid_point], first_values, !forward, comparator);
+        do_sort(&mut x[mid_point..], second_values, forward, comparator);
 
-        sub_sort(x, forward, comparator);
+        sub_sort(x, values, forward, comparator);
     }
 }
 
-fn sub_sort<T, F>(x: &mut [T], forward: bool, comparator: &F)
+fn sub_sort<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F)
     where F: Fn(&T, &T) -> Ordering
 {
     if x.len() > 1 {
-        compare_and_swap(x, forward, comparator);
-        let mid_point = x.len() / 2;
-        sub_sort(&mut x[..mid_point], forward, comparator);
-        sub_sort(&mut x[mid_point..], forward, comparator);
+        compare_and_swap(x, values, forward, comparator);
+        // 要素数が2のべき乗ならx.len() / 2と同じ位置で分割する
+        let mid_point = greatest_power_of_two_less_than(x.len());
+        let (first_values, second_values) = values.split_at_mut(mid_point);
+        sub_sort(&mut x[..mid_point], first_values, forward, comparator);
+        sub_sort(&mut x[mid_point..], second_values, forward, comparator);
     }
 }
 
-fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
+fn compare_and_swap<T, V, F>(x: &mut [T], values: &mut [V], forward: bool, comparator: &F)
     where F: Fn(&T, &T) -> Ordering
 {
     // 比較に先立ちforward(bool)をOrdering値に変換しておく
@@ -47,74 +62,177 @@ fn compare_and_swap<T, F>(x: &mut [T], forward: bool, comparator: &F)
     } else {
         Ordering::Less
     };
-    let mid_point = x.len() / 2;
-    for i in 0..mid_point {
+    // 要素数が2のべき乗でないときは、末尾に仮想的な要素があるものとして
+    // 相手が存在する要素だけを比較する
+    let mid_point = greatest_power_of_two_less_than(x.len());
+    for i in 0..(x.len() - mid_point) {
         // comparatorクロージャで2要素を比較し、返されたOrderingのバリアントが
         // swap_conditionと等しいなら要素を交換する
+        // キーと値を同時に交換するので、2つの列の対応が崩れない
         if comparator(&x[i], &x[mid_point + i]) == swap_condition {
             x.swap(i, mid_point + i);
+            values.swap(i, mid_point + i);
         }
     }
 }
 
-pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), String>
+pub fn sort_by<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
     where F: Fn(&T, &T) -> Ordering
 {
     if x.len().is_power_of_two() {
-        do_sort(x, true, comparator);
+        do_sort(x, &mut no_values(x.len()), true, comparator);
+        // check-sortedフィーチャーを有効にしたときは、ソート結果がcomparatorの順に並んでいることを検証する
+        check_sorted_by(x, comparator);
         Ok(())
     } else {
-        Err(format!("The length of x is not a power of two. (x.len(): {})", x.len()))
+        Err(SortError::NotPowerOfTwo(x.len()))
     }
 }
 
+// 要素数が2のべき乗でなくてもソートできるsort_by
+pub fn sort_by_arbitrary<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where F: Fn(&T, &T) -> Ordering
+{
+    do_sort(x, &mut no_values(x.len()), true, comparator);
+    check_sorted_by(x, comparator);
+    Ok(())
+}
 
-// このモジュールはcargo testを実行したときのみコンパイルされる
-#[cfg(test)]
-mod tests {
-    use super::{sort, sort_by};
-    use crate::SortOrder::*;
-    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+// 要素から取り出したキーでソートする(slice::sort_by_keyに相当)
+// キーは比較のたびに計算される
+pub fn sort_by_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+    where K: Ord,
+          F: Fn(&T) -> K
+{
+    sort_by(x, &|a, b| key(a).cmp(&key(b)))
+}
 
-    // 構造体Studentを定義する
-    // deriveアトリビュートを使い、DebugトレイととPartialEqトレイとの実装を自動導出する
-    #[derive(Debug, PartialEq)]
-    struct Student {
-        first_name: String,
-        last_name: String,
-        age: u8,
+// 要素から取り出したキーでソートする(slice::sort_by_cached_keyに相当)
+// キーは要素ごとに一度だけ計算されるので、キーの計算が重いときに向いている
+// sort_by_arbitraryと同じく、要素数は2のべき乗でなくてよい
+pub fn sort_by_cached_key<T, K, F>(x: &mut [T], key: &F) -> Result<(), SortError>
+    where K: Ord,
+          F: Fn(&T) -> K
+{
+    // キーと元のインデックスの組をソートする
+    // インデックスも比較に含めるので、キーが等しい要素があっても順序が一意に決まる
+    let mut keys: Vec<_> = x.iter().map(key).enumerate().map(|(i, k)| (k, i)).collect();
+    sort_by_arbitrary(&mut keys, &|a, b| a.cmp(b))?;
+
+    let mut perm: Vec<_> = keys.into_iter().map(|(_, i)| i).collect();
+    apply_permutation(x, &mut perm);
+    Ok(())
+}
+
+// 安定ソート。comparatorで等しい要素は、ソート前の順序を保つ(slice::sort_byと同じ結果になる)
+// 要素そのものではなくインデックスの列をソートし、最後に要素を並べ替える
+// インデックスの列はsort_by_arbitraryでソートするので、要素数は2のべき乗でなくてよい
+pub fn sort_by_stable<T, F>(x: &mut [T], comparator: &F) -> Result<(), SortError>
+    where F: Fn(&T, &T) -> Ordering
+{
+    // 等しい要素は元のインデックスで比較するので、順序が入れ替わらない
+    let mut perm: Vec<usize> = (0..x.len()).collect();
+    sort_by_arbitrary(&mut perm, &|&i, &j| comparator(&x[i], &x[j]).then(i.cmp(&j)))?;
+
+    apply_permutation(x, &mut perm);
+    Ok(())
+}
+
+// キーの列keysをソートし、値の列valuesの要素もkeysと同じ位置に並べ替える
+// (キー, 値)のタプルのベクタを作らずに、キーと値の組をソートできる
+pub fn sort_pairs<K: Ord, V>(keys: &mut [K], values: &mut [V], order: &SortOrder)
+    -> Result<(), SortError>
+{
+    match *order {
+        SortOrder::Ascending => sort_pairs_by(keys, values, &|a, b| a.cmp(b)),
+        SortOrder::Descending => sort_pairs_by(keys, values, &|a, b| b.cmp(a)),
     }
+}
 
-    // implブロックを使うと対象の型に関連関数やメソッドを実装できる
-    impl Student {
+pub fn sort_pairs_by<K, V, F>(keys: &mut [K], values: &mut [V], comparator: &F)
+    -> Result<(), SortError>
+    where F: Fn(&K, &K) -> Ordering
+{
+    if keys.len() != values.len() {
+        return Err(SortError::LengthMismatch(keys.len(), values.len()));
+    }
+    if !keys.len().is_power_of_two() {
+        return Err(SortError::NotPowerOfTwo(keys.len()));
+    }
+    do_sort(keys, values, true, comparator);
+    check_sorted_by(keys, comparator);
+    Ok(())
+}
 
-        // 関連関数newを定義する
-        fn new(first_name: &str, last_name: &str, age: u8) -> Self {
+// keysをソートし、ソート後の各要素の元のインデックスを返す
+// 返す列permのperm[i]は、ソート後にi番目に来た要素のソート前の位置になる
+// キーが等しい要素の順序は不定。ソート前の順序を保つにはsort_by_stableを使う
+pub fn argsort<K: Ord>(keys: &mut [K], order: &SortOrder) -> Result<Vec<usize>, SortError> {
+    match *order {
+        SortOrder::Ascending => argsort_by(keys, &|a, b| a.cmp(b)),
+        SortOrder::Descending => argsort_by(keys, &|a, b| b.cmp(a)),
+    }
+}
 
-            Self {
-                // 構造体Studentを初期化して返す。Selfはimpl対象の型(Student)の別名
-                first_name: first_name.to_string(),
-                last_name: last_name.to_string(),
-                age, // フィールドと変数が同じ名前のときは、このように省略することができる
-            }
-        }
+pub fn argsort_by<K, F>(keys: &mut [K], comparator: &F) -> Result<Vec<usize>, SortError>
+    where F: Fn(&K, &K) -> Ordering
+{
+    // インデックスの列を値としてキーと一緒に並べ替える
+    let mut perm: Vec<usize> = (0..keys.len()).collect();
+    sort_pairs_by(keys, &mut perm, comparator)?;
+    Ok(perm)
+}
+
+// orderの順にソート済みの2つの列aとbを、1つのソート済みの列にまとめて返す
+// バイトニックマージを使うので、aとbの要素数に制約はない
+pub fn merge<T: Ord + Clone>(a: &[T], b: &[T], order: &SortOrder) -> Vec<T> {
+    match *order {
+        SortOrder::Ascending => merge_by(a, b, &|x, y| x.cmp(y)),
+        SortOrder::Descending => merge_by(a, b, &|x, y| y.cmp(x)),
     }
+}
+
+// comparatorの順にソート済みの2つの列aとbを、1つのソート済みの列にまとめて返す
+pub fn merge_by<T, F>(a: &[T], b: &[T], comparator: &F) -> Vec<T>
+    where T: Clone,
+          F: Fn(&T, &T) -> Ordering
+{
+    check_sorted_by(a, comparator);
+    check_sorted_by(b, comparator);
+    // aを逆順にしてbの前につなげると、前半が逆順、後半が正順の列になる
+    // do_sortがsub_sortに渡すのと同じ形なので、そのままsub_sortでマージできる
+    let mut x: Vec<T> = a.iter().rev().chain(b).cloned().collect();
+    sub_sort(&mut x, &mut no_values(a.len() + b.len()), true, comparator);
+    x
+}
+
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{sort, sort_by, sort_arbitrary, sort_by_cached_key, sort_by_stable};
+    use super::{merge, do_sort};
+    use super::{sort_pairs, sort_pairs_by, argsort, argsort_by};
+    use crate::no_values;
+    use std::cell::Cell;
+    use crate::SortError;
+    use crate::SortOrder::*;
+    use crate::utils::{new_u32_vec, is_sorted_ascending, is_sorted_descending};
+    use crate::tests::students;
 
     #[test]
     // 年齢で昇順にソートする
     fn sort_students_by_age_ascending() {
 
         // テストデータを作成
-        let taro = Student::new("Taro", "Yamada", 16);
-        let hanako = Student::new("Hanako", "Yamada", 14);
-        let kyoko = Student::new("Kyoko", "Ito", 15);
-        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+        let students = students();
+        let (taro, hanako, kyoko, ryosuke) = (&students[0], &students[1], &students[2], &students[3]);
 
         // ソート対象のベクタを作成する
-        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        let mut x = vec![taro, hanako, kyoko, ryosuke];
 
         // ソート後の期待値を作成する
-        let expected = vec![&hanako, &kyoko, &taro, &ryosuke];
+        let expected = vec![hanako, kyoko, taro, ryosuke];
 
         assert_eq!(
             // sort_by関数でソートする。第二引数はソート順を決めるクロージャ
@@ -131,14 +249,12 @@ mod tests {
     // 名前で昇順にソートする
     fn sort_students_by_name_ascending() {
 
-        let taro = Student::new("Taro", "Yamada", 16);
-        let hanako = Student::new("Hanako", "Yamada", 14);
-        let kyoko = Student::new("Kyoko", "Ito", 15);
-        let ryosuke = Student::new("Ryosuke", "Hayashi", 17);
+        let students = students();
+        let (taro, hanako, kyoko, ryosuke) = (&students[0], &students[1], &students[2], &students[3]);
 
-        let mut x = vec![&taro, &hanako, &kyoko, &ryosuke];
+        let mut x = vec![taro, hanako, kyoko, ryosuke];
 
-        let expected = vec![&ryosuke, &kyoko, &hanako, &taro];
+        let expected = vec![ryosuke, kyoko, hanako, taro];
 
         assert_eq!(
             sort_by(&mut x,
@@ -173,4 +289,140 @@ mod tests {
             
         }
     }
+
+    #[test]
+    fn sort_u32_arbitrary_length() {
+        // 2のべき乗でない要素数のデータ列もソートできる
+        for &n in &[0, 1, 3, 5, 100, 1000, 65535] {
+            let mut x = new_u32_vec(n);
+            assert_eq!(sort_arbitrary(&mut x, &Ascending), Ok(()));
+            assert!(is_sorted_ascending(&x));
+
+            let mut x = new_u32_vec(n);
+            assert_eq!(sort_arbitrary(&mut x, &Descending), Ok(()));
+            assert!(is_sorted_descending(&x));
+        }
+    }
+
+    #[test]
+    fn sort_error_propagates() {
+        // SortErrorはstd::error::Errorを実装しているので、?演算子でBox<dyn Error>へ変換できる
+        fn sort_three() -> Result<(), Box<dyn std::error::Error>> {
+            let mut x = vec![10, 30, 11];
+            sort(&mut x, &Ascending)?;
+            Ok(())
+        }
+
+        let err = sort_three().unwrap_err();
+        assert_eq!(err.downcast_ref::<SortError>(), Some(&SortError::NotPowerOfTwo(3)));
+    }
+
+    #[test]
+    fn sort_u32_by_cached_key_large() {
+        let mut x = new_u32_vec(65536);
+        // 降順になるようなキーを与える
+        assert_eq!(sort_by_cached_key(&mut x, &|&v| std::cmp::Reverse(v)), Ok(()));
+        assert!(is_sorted_descending(&x));
+
+        // 要素数が2のべき乗でなくてもよい
+        let mut x = new_u32_vec(1000);
+        assert_eq!(sort_by_cached_key(&mut x, &|&v| v), Ok(()));
+        assert!(is_sorted_ascending(&x));
+    }
+
+    #[test]
+    fn sort_u32_stable_matches_std() {
+        // 値の種類が少ない列を、上位の桁だけで比べて安定ソートする
+        let x = new_u32_vec(65536);
+        let mut expected = x.clone();
+        expected.sort_by_key(|v| v >> 28);
+
+        let mut y = x.clone();
+        assert_eq!(sort_by_stable(&mut y, &|a, b| (a >> 28).cmp(&(b >> 28))), Ok(()));
+        assert_eq!(y, expected);
+
+        // 要素数が2のべき乗でなくてもよい
+        let x = new_u32_vec(1000);
+        let mut expected = x.clone();
+        expected.sort_by_key(|v| v >> 30);
+        let mut y = x.clone();
+        assert_eq!(sort_by_stable(&mut y, &|a, b| (a >> 30).cmp(&(b >> 30))), Ok(()));
+        assert_eq!(y, expected);
+    }
+
+    #[test]
+    fn merge_sorted_runs() {
+        // 長さの異なるソート済みの列をマージする
+        for &(n, m) in &[(0, 0), (0, 5), (1, 0), (3, 5), (100, 37), (1000, 12345)] {
+            let mut a = new_u32_vec(n);
+            let mut b: Vec<u32> = new_u32_vec(n + m)[n..].to_vec();
+            let mut expected: Vec<u32> = a.iter().chain(&b).cloned().collect();
+
+            a.sort();
+            b.sort();
+            expected.sort();
+            assert_eq!(merge(&a, &b, &Ascending), expected);
+
+            a.reverse();
+            b.reverse();
+            expected.reverse();
+            assert_eq!(merge(&a, &b, &Descending), expected);
+        }
+    }
+
+    // 16要素以下の部分列を固定長のネットワークでソートすると、比較の回数が減る
+    // 1024要素のバイトニックソートは28,160回比較する。16要素の部分列64個それぞれで
+    // 比較が80回から60回に減るので、全体では1,280回少なくなる
+    #[test]
+    fn small_networks_reduce_comparisons() {
+        let count = Cell::new(0usize);
+        let mut x = new_u32_vec(1024);
+        do_sort(&mut x, &mut no_values(1024), true, &|a: &u32, b: &u32| { count.set(count.get() + 1); a.cmp(b) });
+        assert!(is_sorted_ascending(&x));
+        assert_eq!(count.get(), 28_160 - 64 * 20);
+    }
+
+    #[test]
+    fn sort_pairs_by_age() {
+        let mut ages = vec![16, 15, 17, 14];
+        let mut names = vec!["Taro", "Hanako", "Kyoko", "Ryosuke"];
+        assert_eq!(sort_pairs(&mut ages, &mut names, &Ascending), Ok(()));
+        assert_eq!(ages, vec![14, 15, 16, 17]);
+        assert_eq!(names, vec!["Ryosuke", "Hanako", "Taro", "Kyoko"]);
+
+        assert_eq!(sort_pairs_by(&mut ages, &mut names, &|a: &i32, b: &i32| b.cmp(a)), Ok(()));
+        assert_eq!(names, vec!["Kyoko", "Taro", "Hanako", "Ryosuke"]);
+    }
+
+    #[test]
+    fn sort_pairs_u32_large() {
+        let original = new_u32_vec(65536);
+        let mut keys = original.clone();
+        let mut values: Vec<u64> = original.iter().map(|&k| k as u64 * 3).collect();
+        assert_eq!(sort_pairs(&mut keys, &mut values, &Descending), Ok(()));
+        assert!(is_sorted_descending(&keys));
+        assert!(keys.iter().zip(&values).all(|(&k, &v)| v == k as u64 * 3));
+    }
+
+    #[test]
+    fn argsort_u32() {
+        let mut x = vec![10, 30, 11, 20];
+        assert_eq!(argsort(&mut x, &Ascending), Ok(vec![0, 2, 3, 1]));
+        assert_eq!(x, vec![10, 11, 20, 30]);
+
+        let mut x = vec!["ccc", "a", "dddd", "bb"];
+        assert_eq!(argsort_by(&mut x, &|a: &&str, b: &&str| a.len().cmp(&b.len())), Ok(vec![1, 3, 0, 2]));
+    }
+
+    #[test]
+    fn sort_pairs_errors() {
+        let mut keys = vec![3, 1, 2, 4];
+        let mut values = vec![0; 3];
+        assert_eq!(sort_pairs(&mut keys, &mut values, &Ascending), Err(SortError::LengthMismatch(4, 3)));
+        // 失敗したときは、どちらの列も変更しない
+        assert_eq!(keys, vec![3, 1, 2, 4]);
+
+        let mut keys = vec![3, 1, 2];
+        assert_eq!(argsort(&mut keys, &Ascending), Err(SortError::NotPowerOfTwo(3)));
+    }
 }
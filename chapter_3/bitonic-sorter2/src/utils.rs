@@ -1,6 +1,7 @@
 use rand::{Rng, SeedableRng};
-use rand::distributions::Standard;
+use rand::distributions::{Alphanumeric, Standard, WeightedIndex};
 use rand_pcg::Pcg64Mcg;
+use std::cmp::Ordering;
 
 pub fn new_u32_vec(n: usize) -> Vec<u32> {
     // RNGを初期化する。再現性を持たせるため毎回同じシード値を使う
@@ -12,6 +13,112 @@ pub fn new_u32_vec(n: usize) -> Vec<u32> {
     rng.sample_iter(&Standard).take(n).collect()
 }
 
+// new_vecで生成するデータ列の並び
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    // 一様な乱数
+    Random,
+    // 昇順にソート済み
+    Sorted,
+    // 降順にソート済み
+    ReverseSorted,
+    // 昇順にソート済みの列から、ランダムに選んだ2要素の交換をswaps回行ったもの
+    NearlySorted { swaps: usize },
+    // unique種類の値だけからなる列
+    FewUnique { unique: usize },
+    // 中央に向かって増加し、そこから減少する山型の列
+    OrganPipe,
+    // unique種類の値が、順位のexponent乗に反比例する頻度で現れる列(ジップ分布)
+    Zipf { unique: usize, exponent: f64 },
+}
+
+// new_vecで生成できる要素の型
+pub trait RandomValue: Clone + PartialOrd {
+    fn random<R: Rng>(rng: &mut R) -> Self;
+}
+
+impl RandomValue for u32 {
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        rng.gen()
+    }
+}
+
+impl RandomValue for u64 {
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        rng.gen()
+    }
+}
+
+impl RandomValue for f64 {
+    // 0以上1未満の値を返すので、NaNは含まれない
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        rng.gen()
+    }
+}
+
+impl RandomValue for String {
+    // 1から16文字の英数字からなる文字列を返す
+    fn random<R: Rng>(rng: &mut R) -> Self {
+        let len = rng.gen_range(1, 17);
+        rng.sample_iter(&Alphanumeric).take(len).collect()
+    }
+}
+
+// 指定した並びのデータ列をn要素作る。同じseedからは常に同じデータ列ができる
+pub fn new_vec<T: RandomValue>(n: usize, pattern: Pattern, seed: u64) -> Vec<T> {
+    let mut rng = Pcg64Mcg::seed_from_u64(seed);
+
+    match pattern {
+        Pattern::Random => random_vec(&mut rng, n),
+        Pattern::Sorted => sorted_vec(&mut rng, n),
+        Pattern::ReverseSorted => {
+            let mut x = sorted_vec(&mut rng, n);
+            x.reverse();
+            x
+        }
+        Pattern::NearlySorted { swaps } => {
+            let mut x = sorted_vec(&mut rng, n);
+            if n > 1 {
+                for _ in 0..swaps {
+                    let i = rng.gen_range(0, n);
+                    let j = rng.gen_range(0, n);
+                    x.swap(i, j);
+                }
+            }
+            x
+        }
+        Pattern::FewUnique { unique } => {
+            let values: Vec<T> = random_vec(&mut rng, unique.max(1));
+            (0..n).map(|_| values[rng.gen_range(0, values.len())].clone()).collect()
+        }
+        Pattern::OrganPipe => {
+            // ソート済みの列の偶数番目を昇順に、奇数番目を降順に並べる
+            let sorted = sorted_vec(&mut rng, n);
+            let rising = sorted.iter().step_by(2);
+            let falling = sorted.iter().skip(1).step_by(2).rev();
+            rising.chain(falling).cloned().collect()
+        }
+        Pattern::Zipf { unique, exponent } => {
+            // 順位kの値が選ばれる重みを1 / k^exponentとする
+            let values: Vec<T> = sorted_vec(&mut rng, unique.max(1));
+            let weights = (1..=values.len()).map(|k| 1.0 / (k as f64).powf(exponent));
+            let dist = WeightedIndex::new(weights).expect("exponent must be finite");
+            (0..n).map(|_| values[rng.sample(&dist)].clone()).collect()
+        }
+    }
+}
+
+fn random_vec<T: RandomValue, R: Rng>(rng: &mut R, n: usize) -> Vec<T> {
+    (0..n).map(|_| T::random(rng)).collect()
+}
+
+fn sorted_vec<T: RandomValue, R: Rng>(rng: &mut R, n: usize) -> Vec<T> {
+    let mut x: Vec<T> = random_vec(rng, n);
+    // RandomValueの値はNaNを含まないので、partial_cmpは常に比較結果を返す
+    x.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    x
+}
+
 pub fn is_sorted_ascending<T: Ord>(x: &[T]) -> bool {
     // windows(2)はもとのイテレータから1要素刻みで2要素ずつ値を取り出す
     // [1, 2, 3, 4]なら[1, 2], [2, 3], [3, 4]を順に返す
@@ -25,4 +132,107 @@ pub fn is_sorted_ascending<T: Ord>(x: &[T]) -> bool {
 
 pub fn is_sorted_descending<T: Ord>(x: &[T]) ->bool {
     x.windows(2).all(|pair| pair[0] >= pair[1])
-}
\ No newline at end of file
+}
+
+// comparatorが決める順にソートされているかを調べる
+// sort_byと同じ型のクロージャを受け取るので、sort_byの結果の検証にそのまま使える
+pub fn is_sorted_by<T, F>(x: &[T], comparator: &F) -> bool
+    where F: Fn(&T, &T) -> Ordering
+{
+    // 隣り合う2要素が逆順(Greater)になっていなければソート済み
+    x.windows(2).all(|pair| comparator(&pair[0], &pair[1]) != Ordering::Greater)
+}
+
+// keyで取り出したキーの昇順にソートされているかを調べる
+pub fn is_sorted_by_key<T, K, F>(x: &[T], key: &F) -> bool
+    where K: Ord,
+          F: Fn(&T) -> K
+{
+    is_sorted_by(x, &|a, b| key(a).cmp(&key(b)))
+}
+
+// check-sortedフィーチャーを有効にしたときだけ、xがcomparatorの順にソートされていることを検証する
+// 検証でもcomparatorを呼ぶので、既定では何もせず、比較の回数やcomparatorの副作用を変えない
+pub(crate) fn check_sorted_by<T, F>(x: &[T], comparator: &F)
+    where F: Fn(&T, &T) -> Ordering
+{
+    if cfg!(feature = "check-sorted") {
+        assert!(is_sorted_by(x, comparator), "the result is not sorted by the comparator");
+    }
+}
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::{new_vec, Pattern, is_sorted_ascending, is_sorted_descending};
+    use super::{is_sorted_by, is_sorted_by_key};
+
+    const N: usize = 1000;
+
+    #[test]
+    fn same_seed_same_data() {
+        let x: Vec<String> = new_vec(N, Pattern::Random, 42);
+        let y: Vec<String> = new_vec(N, Pattern::Random, 42);
+        let z: Vec<String> = new_vec(N, Pattern::Random, 43);
+        assert_eq!(x, y);
+        assert_ne!(x, z);
+        assert!(x.iter().all(|s| (1..=16).contains(&s.len())));
+    }
+
+    #[test]
+    fn sorted_patterns() {
+        let x: Vec<u64> = new_vec(N, Pattern::Sorted, 1);
+        assert!(is_sorted_ascending(&x));
+        let x: Vec<u32> = new_vec(N, Pattern::ReverseSorted, 1);
+        assert!(is_sorted_descending(&x));
+        let x: Vec<f64> = new_vec(N, Pattern::Sorted, 1);
+        assert!(x.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // 10回の交換で、昇順でない隣接要素の組は高々40個
+        let x: Vec<u32> = new_vec(N, Pattern::NearlySorted { swaps: 10 }, 1);
+        let inversions = x.windows(2).filter(|pair| pair[0] > pair[1]).count();
+        assert!(0 < inversions && inversions <= 40);
+    }
+
+    #[test]
+    fn organ_pipe_rises_then_falls() {
+        let x: Vec<u32> = new_vec(N, Pattern::OrganPipe, 1);
+        let peak = (0..N).max_by_key(|&i| x[i]).unwrap();
+        assert!(is_sorted_ascending(&x[..=peak]));
+        assert!(is_sorted_descending(&x[peak..]));
+    }
+
+    #[test]
+    fn few_unique_and_zipf() {
+        let mut x: Vec<u32> = new_vec(N, Pattern::FewUnique { unique: 5 }, 1);
+        x.sort();
+        x.dedup();
+        assert!(x.len() <= 5);
+
+        // 順位1の値(最小値)が最も多く現れる
+        let x: Vec<u64> = new_vec(N, Pattern::Zipf { unique: 100, exponent: 1.5 }, 1);
+        let min = *x.iter().min().unwrap();
+        let max = *x.iter().max().unwrap();
+        let count = |v| x.iter().filter(|&&w| w == v).count();
+        assert!(count(min) > N / 4);
+        assert!(count(min) > count(max));
+    }
+
+    #[test]
+    fn sorted_by_comparator_and_key() {
+        let x = vec![(1, "b"), (1, "a"), (2, "c"), (3, "a")];
+        assert!(is_sorted_by(&x, &|a: &(i32, &str), b| a.0.cmp(&b.0)));
+        assert!(!is_sorted_by(&x, &|a: &(i32, &str), b| a.cmp(b)));
+        assert!(is_sorted_by_key(&x, &|a| a.0));
+        assert!(!is_sorted_by_key(&x, &|a| a.1));
+
+        // 降順の比較関数も使える
+        let x: Vec<u32> = new_vec(N, Pattern::ReverseSorted, 1);
+        assert!(is_sorted_by(&x, &|a, b| b.cmp(a)));
+        assert!(is_sorted_by_key(&x, &|&a| std::cmp::Reverse(a)));
+
+        // 要素数が0や1ならソート済み
+        assert!(is_sorted_by(&Vec::<u32>::new(), &|a, b| a.cmp(b)));
+        assert!(is_sorted_by(&[1], &|a: &i32, b| a.cmp(b)));
+    }
+}
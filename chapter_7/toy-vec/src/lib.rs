@@ -1,3 +1,5 @@
+use std::ops::{Bound, RangeBounds};
+
 pub struct ToyVec<T> {
     elements: Box<[T]>, // T型の要素を格納する領域。各要素はヒープ領域に置かれる
     len: usize,         // ベクタの長さ(現在の要素数)
@@ -100,6 +102,139 @@ impl<T: Default> ToyVec<T> {
         }
     }
 
+    // 以降のメソッドはVec<T>の同名のメソッドと同じように振る舞う
+    // 計算量のnはベクタの長さを表す
+
+    // index番目に要素を挿入し、それ以降の要素を後ろにずらす
+    // index > lenならパニックする。計算量はO(n - index)
+    pub fn insert(&mut self, index: usize, element: T) {
+        assert!(index <= self.len, "insertion index (is {}) should be <= len (is {})", index, self.len);
+        if self.len == self.capacity() {
+            self.grow();
+        }
+        // 末尾に置いてから、index番目まで回転させる
+        self.elements[self.len] = element;
+        self.elements[index..=self.len].rotate_right(1);
+        self.len += 1;
+    }
+
+    // index番目の要素を取り除いて返し、それ以降の要素を前にずらす
+    // index >= lenならパニックする。計算量はO(n - index)
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "removal index (is {}) should be < len (is {})", index, self.len);
+        // 取り除く要素を末尾まで回転させてから、popと同じように取り出す
+        self.elements[index..self.len].rotate_left(1);
+        self.len -= 1;
+        std::mem::take(&mut self.elements[self.len])
+    }
+
+    // index番目の要素を取り除いて返し、空いた場所に末尾の要素を移す
+    // 要素の順序は保たれない。index >= lenならパニックする。計算量はO(1)
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "swap_remove index (is {}) should be < len (is {})", index, self.len);
+        self.len -= 1;
+        self.elements.swap(index, self.len);
+        std::mem::take(&mut self.elements[self.len])
+    }
+
+    // 先頭のlen個の要素を残し、残りを取り除く。キャパシティは変わらない
+    // lenが現在の長さ以上なら何もしない。計算量はO(取り除く要素数)
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            // 取り除いた要素はデフォルト値と置き換えた時点でドロップされる
+            self.pop();
+        }
+    }
+
+    // すべての要素を取り除く。キャパシティは変わらない。計算量はO(n)
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    // 少なくともadditional個の要素を追加で格納できるように、キャパシティを増やす
+    // 足りているときは何もしない。計算量は領域を確保し直したときO(n)、それ以外はO(1)
+    pub fn reserve(&mut self, additional: usize) {
+        let required = self.len.checked_add(additional).expect("capacity overflow");
+        if required > self.capacity() {
+            // pushを繰り返すときと同じく、少なくとも現在の2倍に増やす
+            self.reallocate(required.max(self.capacity() * 2));
+        }
+    }
+
+    // キャパシティを長さと同じにする。計算量はO(n)
+    pub fn shrink_to_fit(&mut self) {
+        if self.capacity() > self.len {
+            self.reallocate(self.len);
+        }
+    }
+
+    // otherのすべての要素を末尾にムーブし、otherを空にする
+    // 計算量はO(otherの長さ)。キャパシティが足りなければO(n + otherの長さ)
+    pub fn append(&mut self, other: &mut Self) {
+        self.reserve(other.len);
+        for elem in other.drain(..) {
+            self.push(elem);
+        }
+    }
+
+    // at番目以降の要素を新しいToyVecに移して返す。selfのキャパシティは変わらない
+    // at > lenならパニックする。計算量はO(n - at)
+    pub fn split_off(&mut self, at: usize) -> Self {
+        assert!(at <= self.len, "`at` split index (is {}) should be <= len (is {})", at, self.len);
+        let mut other = Self::with_capacity(self.len - at);
+        for elem in self.drain(at..) {
+            other.push(elem);
+        }
+        other
+    }
+
+    // fがtrueを返す要素だけを、順序を保ったまま残す
+    // fは各要素に対して先頭から1回ずつ呼ばれる。計算量はO(n)
+    pub fn retain<F>(&mut self, mut f: F)
+        where F: FnMut(&T) -> bool
+    {
+        // 残す要素を前に詰めていき、取り除く要素を後ろに集める
+        let mut kept = 0;
+        for i in 0..self.len {
+            if f(&self.elements[i]) {
+                self.elements.swap(kept, i);
+                kept += 1;
+            }
+        }
+        self.truncate(kept);
+    }
+
+    // rangeの範囲の要素を取り除き、それらを先頭から順に返すイテレータを返す
+    // 範囲より後ろの要素は前にずらす。イテレータを最後まで使わなくても、範囲の要素はすべて取り除かれる
+    // 範囲の始点が終点より大きいか、終点がlenより大きいならパニックする。計算量はO(n - 範囲の始点)
+    pub fn drain<R>(&mut self, range: R) -> Drain<'_, T>
+        where R: RangeBounds<usize>
+    {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1).expect("range start overflow"),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1).expect("range end overflow"),
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+        assert!(start <= end, "slice index starts at {} but ends at {}", start, end);
+        assert!(end <= self.len, "range end index {} out of range for slice of length {}", end, self.len);
+
+        // 取り除く要素を、順序を保ったまま末尾に回転させる
+        // 長さを縮めておき、キャパシティの余りに移った要素をDrainが取り出す
+        let old_len = self.len;
+        self.elements[start..old_len].rotate_left(end - start);
+        self.len = old_len - (end - start);
+        Drain {
+            elements: &mut self.elements[self.len..old_len],
+            front: 0,
+            back: end - start,
+        }
+    }
+
     fn grow(&mut self) {
         if self.capacity() == 0 {
             self.elements = Self::allocate_in_heap(1);
@@ -117,6 +252,20 @@ impl<T: Default> ToyVec<T> {
         }
     }
 
+    // reserveとshrink_to_fitのために、キャパシティがcapacityの領域を確保し直して要素をムーブする
+    // growと違いキャパシティの余りは空きなので、len個の要素だけを移す。capacityはlen以上であること
+    fn reallocate(&mut self, capacity: usize) {
+        let new_elements = Self::allocate_in_heap(capacity);
+        // self.elementsを置き換える
+        let old_elements = std::mem::replace(&mut self.elements, new_elements);
+
+        // 既存の要素を新しい領域へムーブする
+        // Vec<T>のinto_iter(self)なら、要素の所有権が得られる
+        for (i, elem) in old_elements.into_vec().into_iter().take(self.len).enumerate() {
+            self.elements[i] = elem;
+        }
+    }
+
     pub fn iter<'vec>(&'vec self) -> Iter<'vec, T> {
         Iter {
             elements: &self.elements,       // Iter構造体の定義より、ライフタイムは'vecになる
@@ -126,6 +275,77 @@ impl<T: Default> ToyVec<T> {
     }
 }
 
+// イテレータの要素を末尾に追加する
+// 計算量はO(追加する要素数)。キャパシティが足りなければO(n + 追加する要素数)
+impl<T: Default> Extend<T> for ToyVec<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        // 要素数の下限がわかれば、先にまとめて領域を確保しておく
+        self.reserve(iter.size_hint().0);
+        for elem in iter {
+            self.push(elem);
+        }
+    }
+}
+
+// Copyできる要素なら、参照のイテレータからも追加できる
+impl<'a, T: Default + Copy + 'a> Extend<&'a T> for ToyVec<T> {
+    fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied());
+    }
+}
+
+// drainが返すイテレータ。ToyVecから取り除いた要素を所有権ごと返す
+// 取り除いた要素はToyVecのキャパシティの余りに置かれていて、
+// このイテレータが生存している間はToyVecを変更できない
+pub struct Drain<'vec, T: Default> {
+    elements: &'vec mut [T],    // 取り除いた要素を指す可変の参照
+    front: usize,               // 次に先頭から返す要素のインデックス
+    back: usize,                // 最後に末尾から返した要素のインデックス
+}
+
+impl<'vec, T: Default> Iterator for Drain<'vec, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            None
+        } else {
+            // 要素の所有権を奪い、代わりにデフォルト値を置いておく
+            let elem = std::mem::take(&mut self.elements[self.front]);
+            self.front += 1;
+            Some(elem)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<'vec, T: Default> DoubleEndedIterator for Drain<'vec, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front >= self.back {
+            None
+        } else {
+            self.back -= 1;
+            Some(std::mem::take(&mut self.elements[self.back]))
+        }
+    }
+}
+
+impl<'vec, T: Default> ExactSizeIterator for Drain<'vec, T> {}
+
+// 最後まで使われなかった要素は、イテレータがドロップされたときにドロップする
+impl<'vec, T: Default> Drop for Drain<'vec, T> {
+    fn drop(&mut self) {
+        for elem in &mut self.elements[self.front..self.back] {
+            *elem = Default::default();
+        }
+    }
+}
+
 // Iter<T>にIteratorトレイトを実装する
 impl<'vec, T> Iterator for Iter<'vec, T> {
     // 関連型(トレイトに関連づいた型)で、このイテレータがいてレートする要素の型を指定する
@@ -143,3 +363,141 @@ impl<'vec, T> Iterator for Iter<'vec, T> {
         }
     }
 }
+
+// このモジュールはcargo testを実行したときのみコンパイルされる
+#[cfg(test)]
+mod tests {
+    use super::ToyVec;
+
+    fn toy_vec(v: &[i32]) -> ToyVec<i32> {
+        let mut t = ToyVec::new();
+        t.extend(v);
+        t
+    }
+
+    fn to_vec(t: &ToyVec<i32>) -> Vec<i32> {
+        t.iter().cloned().collect()
+    }
+
+    #[test]
+    fn insert_and_remove_like_vec() {
+        let mut v = vec![1, 2, 3];
+        let mut t = toy_vec(&v);
+        v.insert(1, 10);
+        t.insert(1, 10);
+        v.insert(4, 20);
+        t.insert(4, 20);
+        assert_eq!(to_vec(&t), v);
+
+        assert_eq!(t.remove(0), v.remove(0));
+        assert_eq!(t.swap_remove(0), v.swap_remove(0));
+        assert_eq!(to_vec(&t), v);
+        assert_eq!(to_vec(&t), vec![20, 2, 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_bounds() {
+        toy_vec(&[1, 2]).insert(3, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn remove_out_of_bounds() {
+        toy_vec(&[1, 2]).remove(2);
+    }
+
+    #[test]
+    fn truncate_and_clear_keep_capacity() {
+        let mut t = toy_vec(&[1, 2, 3, 4, 5]);
+        let capacity = t.capacity();
+        t.truncate(10);
+        assert_eq!(t.len(), 5);
+        t.truncate(2);
+        assert_eq!(to_vec(&t), vec![1, 2]);
+        t.clear();
+        assert!(t.len() == 0);
+        assert_eq!(t.capacity(), capacity);
+    }
+
+    #[test]
+    fn reserve_and_shrink_to_fit() {
+        let mut t = toy_vec(&[1, 2, 3]);
+        t.reserve(100);
+        assert!(t.capacity() >= 103);
+        assert_eq!(to_vec(&t), vec![1, 2, 3]);
+        t.shrink_to_fit();
+        assert_eq!(t.capacity(), 3);
+        assert_eq!(to_vec(&t), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn extend_append_and_split_off() {
+        let mut t = toy_vec(&[1, 2]);
+        t.extend(vec![3, 4]);
+        let mut other = toy_vec(&[5, 6]);
+        t.append(&mut other);
+        assert_eq!(to_vec(&t), vec![1, 2, 3, 4, 5, 6]);
+        assert!(other.len() == 0);
+
+        let capacity = t.capacity();
+        let tail = t.split_off(4);
+        assert_eq!(to_vec(&t), vec![1, 2, 3, 4]);
+        assert_eq!(to_vec(&tail), vec![5, 6]);
+        assert_eq!(t.capacity(), capacity);
+        assert!(t.split_off(4).len() == 0);
+    }
+
+    #[test]
+    fn retain_visits_in_order() {
+        let mut v: Vec<i32> = (0..10).collect();
+        let mut t = toy_vec(&v);
+        let mut visited = Vec::new();
+        t.retain(|&x| {
+            visited.push(x);
+            x % 3 != 0
+        });
+        v.retain(|&x| x % 3 != 0);
+        assert_eq!(to_vec(&t), v);
+        assert_eq!(visited, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn drain_like_vec() {
+        let mut v: Vec<i32> = (0..8).collect();
+        let mut t = toy_vec(&v);
+        assert_eq!(t.drain(2..5).collect::<Vec<_>>(), v.drain(2..5).collect::<Vec<_>>());
+        assert_eq!(to_vec(&t), v);
+
+        // 途中までしか使わなくても、範囲の要素はすべて取り除かれる
+        let mut drain = t.drain(1..=3);
+        assert_eq!(drain.len(), 3);
+        assert_eq!(drain.next_back(), Some(6));
+        assert_eq!(drain.next(), Some(1));
+        drop(drain);
+        assert_eq!(to_vec(&t), vec![0, 7]);
+
+        assert_eq!(t.drain(..).collect::<Vec<_>>(), vec![0, 7]);
+        assert!(t.len() == 0);
+    }
+
+    #[test]
+    fn drain_drops_elements() {
+        // 取り除いた要素は、ToyVecに残らずドロップされる
+        let shared = std::rc::Rc::new(());
+        let mut t = ToyVec::new();
+        for _ in 0..4 {
+            t.push(Some(shared.clone()));
+        }
+        t.drain(1..3);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 3);
+        t.truncate(1);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn drain_out_of_bounds() {
+        toy_vec(&[1, 2]).drain(1..3);
+    }
+}